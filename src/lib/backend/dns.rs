@@ -0,0 +1,32 @@
+use crate::backend::{Backend, OutputFile};
+use crate::error::LabBuilderError;
+use crate::name_resolution;
+use crate::resolved_scenario::ResolvedScenario;
+
+use std::path::PathBuf;
+
+/// Generates a hosts file fragment and a dnsmasq config naming every system in the lab domain.
+pub struct DnsBackend;
+
+impl Backend for DnsBackend {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
+
+    fn description(&self) -> &'static str {
+        "hosts file fragment and dnsmasq config for the lab domain"
+    }
+
+    fn generate(&self, scenario: &ResolvedScenario) -> Result<Vec<OutputFile>, LabBuilderError> {
+        Ok(vec![
+            OutputFile {
+                path: PathBuf::from("hosts"),
+                contents: name_resolution::hosts_fragment(scenario),
+            },
+            OutputFile {
+                path: PathBuf::from("dnsmasq.conf"),
+                contents: name_resolution::dnsmasq_config(scenario),
+            },
+        ])
+    }
+}
@@ -0,0 +1,435 @@
+use crate::backend::{self, Backend, OutputFile};
+use crate::error::{LabBuilderError, Location};
+use crate::network::{Network, NetworkType};
+use crate::resolved_scenario::ResolvedScenario;
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+/// Generates a `docker-compose.yml` running every system as a container.
+pub struct DockerComposeBackend;
+
+impl Backend for DockerComposeBackend {
+    fn name(&self) -> &'static str {
+        "docker-compose"
+    }
+
+    fn description(&self) -> &'static str {
+        "docker-compose.yml running systems as containers"
+    }
+
+    fn generate(&self, scenario: &ResolvedScenario) -> Result<Vec<OutputFile>, LabBuilderError> {
+        Ok(vec![OutputFile {
+            path: PathBuf::from("docker-compose.yml"),
+            contents: to_docker_compose(scenario)?,
+        }])
+    }
+}
+
+#[derive(Serialize)]
+struct ComposeFile {
+    version: &'static str,
+    services: Mapping,
+    #[serde(skip_serializing_if = "Mapping::is_empty")]
+    networks: Mapping,
+}
+
+#[derive(Serialize)]
+struct Service {
+    image: String,
+    hostname: String,
+    /// Compose file version 2.4 can only set a MAC address for a whole service, not per network,
+    /// so it's only set for services on a single network, which it can't be mistaken for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mac_address: Option<String>,
+    networks: Mapping,
+}
+
+#[derive(Serialize)]
+struct ServiceNetwork {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv4_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6_address: Option<String>,
+}
+
+/// Networks are left unnamed, so Compose scopes them to the project and two labs with a network
+/// of the same name don't share it.
+#[derive(Serialize)]
+struct ComposeNetwork {
+    driver: &'static str,
+    internal: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    enable_ipv6: bool,
+    ipam: Ipam,
+}
+
+#[derive(Serialize)]
+struct Ipam {
+    config: Vec<IpamConfig>,
+}
+
+#[derive(Serialize)]
+struct IpamConfig {
+    subnet: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gateway: Option<String>,
+}
+
+/// Public networks are mapped to the default bridge network Compose creates for the project.
+const PUBLIC_NETWORK: &str = "default";
+
+pub fn to_docker_compose(scenario: &ResolvedScenario) -> Result<String, LabBuilderError> {
+    let addressed_networks: Vec<(usize, &Network)> = scenario
+        .networks
+        .iter()
+        .map(|net| net.as_ref())
+        .enumerate()
+        .filter(|(_, net)| net.network_type != NetworkType::Public)
+        .collect();
+
+    let network_keys = backend::unique_names(
+        addressed_networks.iter().map(|(_, net)| net.name.as_str()),
+        &[PUBLIC_NETWORK],
+    );
+    let service_keys = backend::unique_names(
+        scenario.systems.iter().map(|system| system.name.as_str()),
+        &[],
+    );
+
+    let mut networks = Mapping::new();
+    for ((index, net), key) in addressed_networks.iter().zip(network_keys.iter()) {
+        let mut config = Vec::new();
+        if let Some(subnet) = net.subnet {
+            config.push(IpamConfig {
+                subnet: subnet.trunc().to_string(),
+                gateway: Some(
+                    bridge_gateway(scenario, net, &format!("networks[{}]", index))?.to_string(),
+                ),
+            });
+        }
+        if let Some(ipv6_subnet) = net.ipv6_subnet {
+            config.push(IpamConfig {
+                subnet: ipv6_subnet.trunc().to_string(),
+                gateway: Some(
+                    bridge_ipv6_gateway(scenario, net, &format!("networks[{}]", index))?
+                        .to_string(),
+                ),
+            });
+        }
+
+        networks.insert(
+            Value::from(key.as_str()),
+            to_value(ComposeNetwork {
+                driver: "bridge",
+                // Only NAT networks get outbound access. The host can reach containers on any
+                // bridge, so host-only networks are internal too.
+                internal: net.network_type != NetworkType::Nat,
+                enable_ipv6: net.ipv6_subnet.is_some(),
+                ipam: Ipam { config },
+            })?,
+        );
+    }
+
+    let mut services = Mapping::new();
+    for (system_index, (system, key)) in
+        scenario.systems.iter().zip(service_keys.iter()).enumerate()
+    {
+        let mut service_networks = Mapping::new();
+        let mut macs = Vec::new();
+        for (nic_index, nic) in system.nics.iter().enumerate() {
+            // Falling back to the public network would quietly take the NIC out of isolation.
+            let network_key = match nic.network.network_type {
+                NetworkType::Internal | NetworkType::HostOnly | NetworkType::Nat => {
+                    addressed_networks
+                        .iter()
+                        .position(|(_, net)| net.name == nic.network.name)
+                        .map(|index| network_keys[index].as_str())
+                        .ok_or_else(|| LabBuilderError::UnknownNetworkReference {
+                            location: Location::new(&format!(
+                                "systems[{}].networks[{}]",
+                                system_index, nic_index
+                            )),
+                            system: system.name.to_string(),
+                            network: nic.network.name.to_string(),
+                        })?
+                }
+                NetworkType::Public => PUBLIC_NETWORK,
+            };
+
+            // Containers can only have one address of each family per network, so any further
+            // NICs on the same network are dropped.
+            if service_networks.contains_key(&Value::from(network_key)) {
+                continue;
+            }
+
+            service_networks.insert(
+                Value::from(network_key),
+                to_value(ServiceNetwork {
+                    ipv4_address: nic
+                        .lease
+                        .and_then(|lease| lease.ipv4)
+                        .map(|addr| addr.to_string()),
+                    ipv6_address: nic
+                        .lease
+                        .and_then(|lease| lease.ipv6)
+                        .map(|addr| addr.to_string()),
+                })?,
+            );
+            macs.push(nic.mac);
+        }
+
+        services.insert(
+            Value::from(key.as_str()),
+            to_value(Service {
+                image: system.base_box.to_string(),
+                hostname: key.to_string(),
+                mac_address: match macs.as_slice() {
+                    [mac] => Some(mac.to_string()),
+                    _ => None,
+                },
+                networks: service_networks,
+            })?,
+        );
+    }
+
+    serde_yaml::to_string(&ComposeFile {
+        version: "2.4",
+        services,
+        networks,
+    })
+    .map_err(|e| LabBuilderError::invalid_value("", &e.to_string()))
+}
+
+/// Docker gives the bridge of every network an address, so it needs one no system has leased.
+/// The host IP of a host-only network is used, as the bridge is the host's address on it, then
+/// the network's gateway if it has one, otherwise the highest free host address.
+fn bridge_gateway(
+    scenario: &ResolvedScenario,
+    net: &Network,
+    path: &str,
+) -> Result<Ipv4Addr, LabBuilderError> {
+    if let Some(gateway) = net.host_ip.or(net.gateway) {
+        return Ok(gateway);
+    }
+
+    let leased: HashSet<Ipv4Addr> = scenario
+        .systems
+        .iter()
+        .flat_map(|system| system.nics.iter())
+        .filter(|nic| nic.network.name == net.name)
+        .filter_map(|nic| nic.lease.and_then(|lease| lease.ipv4))
+        .collect();
+
+    net.subnet
+        .and_then(|subnet| {
+            subnet
+                .hosts()
+                .rev()
+                .find(|addr| !leased.contains(addr) && !net.is_excluded(*addr))
+        })
+        .ok_or_else(|| LabBuilderError::SubnetExhausted {
+            location: Location::new(path),
+            network: net.name.to_string(),
+        })
+}
+
+/// The IPv6 counterpart of `bridge_gateway`. Left to itself Docker would take the first address
+/// of the prefix, which is the first one leased to a system, so the highest free address is used.
+fn bridge_ipv6_gateway(
+    scenario: &ResolvedScenario,
+    net: &Network,
+    path: &str,
+) -> Result<Ipv6Addr, LabBuilderError> {
+    let leased: HashSet<Ipv6Addr> = scenario
+        .systems
+        .iter()
+        .flat_map(|system| system.nics.iter())
+        .filter(|nic| nic.network.name == net.name)
+        .filter_map(|nic| nic.lease.and_then(|lease| lease.ipv6))
+        .collect();
+
+    net.ipv6_subnet
+        .and_then(|subnet| {
+            subnet
+                .hosts()
+                .rev()
+                .find(|addr| !leased.contains(addr) && *addr != subnet.network())
+        })
+        .ok_or_else(|| LabBuilderError::SubnetExhausted {
+            location: Location::new(path),
+            network: net.name.to_string(),
+        })
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, LabBuilderError> {
+    serde_yaml::to_value(value).map_err(|e| LabBuilderError::invalid_value("", &e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    fn compose_output() -> Result<Value, std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Web Server"
+            networks = ["LAN", "Internet"]
+            base_box = "nginx:latest"
+
+            [[systems]]
+            name = "Database"
+            networks = ["LAN"]
+            base_box = "postgres:13"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/64"
+
+            [[networks]]
+            name = "Internet"
+            type = "Public"
+        "#
+        .parse::<toml::Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        Ok(serde_yaml::from_str(&to_docker_compose(&scenario)?)?)
+    }
+
+    #[test]
+    fn compose_output_maps_systems_to_services_with_leased_addresses(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let compose = compose_output()?;
+        let web = &compose["services"]["web-server"];
+
+        assert_eq!(web["image"], Value::from("nginx:latest"));
+        assert_eq!(
+            web["networks"]["lan"]["ipv4_address"],
+            Value::from("192.168.0.1")
+        );
+        assert_eq!(
+            web["networks"]["lan"]["ipv6_address"],
+            Value::from("fd00::1")
+        );
+        assert!(web["networks"]["default"].is_mapping());
+        assert_eq!(
+            compose["services"]["database"]["networks"]["lan"]["ipv4_address"],
+            Value::from("192.168.0.2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compose_output_should_set_mac_address_of_single_network_services(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let compose = compose_output()?;
+
+        assert_eq!(
+            compose["services"]["database"]["mac_address"],
+            Value::from("3e:f9:2c:98:be:a2")
+        );
+        assert!(compose["services"]["web-server"]
+            .get("mac_address")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn compose_output_maps_addressed_networks_to_isolated_bridges(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let compose = compose_output()?;
+        let lan = &compose["networks"]["lan"];
+
+        assert_eq!(lan["driver"], Value::from("bridge"));
+        assert_eq!(lan["internal"], Value::from(true));
+        assert_eq!(lan["enable_ipv6"], Value::from(true));
+        assert_eq!(
+            lan["ipam"]["config"][0]["subnet"],
+            Value::from("192.168.0.0/24")
+        );
+        assert_eq!(
+            lan["ipam"]["config"][0]["gateway"],
+            Value::from("192.168.0.254")
+        );
+        assert_eq!(lan["ipam"]["config"][1]["subnet"], Value::from("fd00::/64"));
+        assert_eq!(
+            lan["ipam"]["config"][1]["gateway"],
+            Value::from("fd00::ffff:ffff:ffff:ffff")
+        );
+        assert!(compose["networks"]["internet"].is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn compose_output_should_key_networks_by_sanitised_name_without_fixing_their_name(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["Office LAN"]
+            base_box = "debian:bullseye"
+
+            [[networks]]
+            name = "Office LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<toml::Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+        let compose: Value = serde_yaml::from_str(&to_docker_compose(&scenario)?)?;
+
+        assert!(compose["networks"]["office-lan"].is_mapping());
+        assert!(compose["networks"]["office-lan"].get("name").is_none());
+        assert!(compose["services"]["desktop"]["networks"]["office-lan"].is_mapping());
+        Ok(())
+    }
+
+    #[test]
+    fn compose_output_for_nic_on_missing_network_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN"]
+            base_box = "debian:bullseye"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<toml::Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+        scenario.networks.clear();
+
+        assert_eq!(
+            to_docker_compose(&scenario).unwrap_err(),
+            LabBuilderError::UnknownNetworkReference {
+                location: Location::new("systems[0].networks[0]"),
+                system: "Desktop".to_string(),
+                network: "LAN".to_string(),
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use crate::backend::{self, Backend, OutputFile};
+use crate::error::LabBuilderError;
+use crate::indentation_aware_string_builder::IndentationAwareStringBuilder;
+use crate::resolved_scenario::{ResolvedScenario, ResolvedSystem};
+
+use serde_yaml::{Mapping, Value};
+use unicode_casefold::UnicodeCaseFold;
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Generates Ansible inventories in both INI and YAML format.
+pub struct InventoryBackend;
+
+impl Backend for InventoryBackend {
+    fn name(&self) -> &'static str {
+        "inventory"
+    }
+
+    fn description(&self) -> &'static str {
+        "Ansible inventory in INI and YAML format"
+    }
+
+    fn generate(&self, scenario: &ResolvedScenario) -> Result<Vec<OutputFile>, LabBuilderError> {
+        let inventory = Inventory::from_scenario(scenario);
+
+        Ok(vec![
+            OutputFile {
+                path: PathBuf::from("inventory.ini"),
+                contents: inventory.to_ini(),
+            },
+            OutputFile {
+                path: PathBuf::from("inventory.yml"),
+                contents: inventory.to_yaml()?,
+            },
+        ])
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Inventory {
+    pub hosts: Vec<Host>,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Host {
+    pub name: String,
+    pub ansible_host: Option<IpAddr>,
+}
+
+/// A group of hosts, which refers to its members by their index in `Inventory::hosts`.
+#[derive(Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub hosts: Vec<usize>,
+}
+
+/// Groups Ansible creates itself, which can't be redefined.
+const IMPLICIT_GROUPS: &[&str] = &["all", "ungrouped"];
+
+impl Inventory {
+    /// Builds a host for every system and a group for every network and tag. Groups are named
+    /// after their network or tag, so a tag sharing a network's name adds to that network's group.
+    pub fn from_scenario(scenario: &ResolvedScenario) -> Inventory {
+        let names = backend::unique_names(scenario.systems.iter().map(|s| s.name.as_str()), &[]);
+
+        let hosts = scenario
+            .systems
+            .iter()
+            .zip(names)
+            .map(|(system, name)| Host {
+                name,
+                ansible_host: management_address(system, scenario.management_network.as_deref()),
+            })
+            .collect();
+
+        let mut inventory = Inventory {
+            hosts,
+            groups: Vec::new(),
+        };
+
+        for net in scenario.networks.iter() {
+            for (index, system) in scenario.systems.iter().enumerate() {
+                if system.nics.iter().any(|nic| nic.network.name == net.name) {
+                    inventory.add_to_group(&net.name, index);
+                }
+            }
+        }
+
+        for (index, system) in scenario.systems.iter().enumerate() {
+            for tag in system.tags.iter() {
+                inventory.add_to_group(tag, index);
+            }
+        }
+
+        inventory
+    }
+
+    fn add_to_group(&mut self, name: &str, host: usize) {
+        let name = group_name(name);
+        match self.groups.iter_mut().find(|group| group.name == name) {
+            Some(group) => {
+                if !group.hosts.contains(&host) {
+                    group.hosts.push(host);
+                }
+            }
+            None => self.groups.push(Group {
+                name,
+                hosts: vec![host],
+            }),
+        }
+    }
+
+    /// Hosts and their variables are listed before the first section, then each group gets a
+    /// section listing its members.
+    pub fn to_ini(&self) -> String {
+        let mut builder = IndentationAwareStringBuilder::new();
+
+        for host in self.hosts.iter() {
+            match host.ansible_host {
+                Some(address) => builder.add(format!("{} ansible_host={}", host.name, address)),
+                None => builder.add(host.name.to_string()),
+            }
+        }
+
+        for group in self.groups.iter() {
+            builder.add("".to_string());
+            builder.add(format!("[{}]", group.name));
+            for &host in group.hosts.iter() {
+                builder.add(self.hosts[host].name.to_string());
+            }
+        }
+
+        builder.build_string()
+    }
+
+    pub fn to_yaml(&self) -> Result<String, LabBuilderError> {
+        let mut hosts = Mapping::new();
+        for host in self.hosts.iter() {
+            let mut variables = Mapping::new();
+            if let Some(address) = host.ansible_host {
+                variables.insert(
+                    Value::from("ansible_host"),
+                    Value::from(address.to_string()),
+                );
+            }
+            hosts.insert(Value::from(host.name.as_str()), Value::Mapping(variables));
+        }
+
+        let mut children = Mapping::new();
+        for group in self.groups.iter() {
+            let mut members = Mapping::new();
+            for &host in group.hosts.iter() {
+                members.insert(
+                    Value::from(self.hosts[host].name.as_str()),
+                    Value::Mapping(Mapping::new()),
+                );
+            }
+
+            let mut group_mapping = Mapping::new();
+            group_mapping.insert(Value::from("hosts"), Value::Mapping(members));
+            children.insert(
+                Value::from(group.name.as_str()),
+                Value::Mapping(group_mapping),
+            );
+        }
+
+        let mut all = Mapping::new();
+        all.insert(Value::from("hosts"), Value::Mapping(hosts));
+        if !children.is_empty() {
+            all.insert(Value::from("children"), Value::Mapping(children));
+        }
+
+        let mut inventory = Mapping::new();
+        inventory.insert(Value::from("all"), Value::Mapping(all));
+
+        serde_yaml::to_string(&inventory)
+            .map_err(|e| LabBuilderError::invalid_value("", &e.to_string()))
+    }
+}
+
+/// The address Ansible connects to a system on: the first leased address on the management
+/// network, or on any network when no management network was chosen. IPv4 is preferred.
+fn management_address(system: &ResolvedSystem, management_network: Option<&str>) -> Option<IpAddr> {
+    system
+        .nics
+        .iter()
+        .filter(|nic| management_network.is_none_or(|name| nic.network.name == name))
+        .filter_map(|nic| nic.lease)
+        .find_map(|lease| {
+            lease
+                .ipv4
+                .map(IpAddr::V4)
+                .or_else(|| lease.ipv6.map(IpAddr::V6))
+        })
+}
+
+/// Turns a network or tag name into a valid Ansible group name, which may only contain letters,
+/// digits and underscores and can't start with a digit.
+fn group_name(name: &str) -> String {
+    let mut group: String = name
+        .case_fold()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if group.is_empty() || group.starts_with(|c: char| c.is_ascii_digit()) {
+        group.insert(0, '_');
+    }
+    if IMPLICIT_GROUPS.contains(&group.as_str()) {
+        group.push('_');
+    }
+
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    fn inventory(
+        management_network: &str,
+    ) -> Result<Inventory, std::boxed::Box<dyn std::error::Error>> {
+        let input = format!(
+            r#"
+            [scenario]
+            name = "Test scenario"
+            {}
+
+            [[systems]]
+            name = "Web Server"
+            networks = ["Internet", "DMZ", "Management"]
+            base_box = "debian/bullseye64"
+            tags = ["webservers", "Linux"]
+
+            [[systems]]
+            name = "Database"
+            networks = ["Management"]
+            base_box = "debian/bullseye64"
+            tags = ["linux"]
+
+            [[networks]]
+            name = "Internet"
+            type = "Public"
+
+            [[networks]]
+            name = "DMZ"
+            type = "Internal"
+            subnet = "10.0.0.0/24"
+
+            [[networks]]
+            name = "Management"
+            type = "Internal"
+            subnet = "192.168.56.0/24"
+            "#,
+            management_network
+        )
+        .parse::<toml::Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        Ok(Inventory::from_scenario(&scenario))
+    }
+
+    #[test]
+    fn ini_inventory_should_group_hosts_by_network_and_tag(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let expected = "web-server ansible_host=192.168.56.1
+database ansible_host=192.168.56.2
+
+[internet]
+web-server
+
+[dmz]
+web-server
+
+[management]
+web-server
+database
+
+[webservers]
+web-server
+
+[linux]
+web-server
+database";
+
+        assert_eq!(
+            inventory(r#"management_network = "Management""#)?.to_ini(),
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ansible_host_should_fall_back_to_first_leased_address(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let inventory = inventory("")?;
+
+        assert_eq!(
+            inventory.hosts[0].ansible_host,
+            Some("10.0.0.1".parse::<IpAddr>()?)
+        );
+        assert_eq!(
+            inventory.hosts[1].ansible_host,
+            Some("192.168.56.2".parse::<IpAddr>()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_inventory_should_list_hosts_and_group_children(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let yaml: Value =
+            serde_yaml::from_str(&inventory(r#"management_network = "Management""#)?.to_yaml()?)?;
+
+        assert_eq!(
+            yaml["all"]["hosts"]["database"]["ansible_host"],
+            Value::from("192.168.56.2")
+        );
+        assert!(yaml["all"]["children"]["webservers"]["hosts"]["web-server"].is_mapping());
+        assert!(yaml["all"]["children"]["webservers"]["hosts"]["database"].is_null());
+        Ok(())
+    }
+}
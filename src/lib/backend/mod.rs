@@ -0,0 +1,159 @@
+pub mod dns;
+pub mod docker_compose;
+pub mod inventory;
+pub mod vagrant;
+
+use crate::error::LabBuilderError;
+use crate::resolved_scenario::ResolvedScenario;
+use crate::unique_names;
+
+use unicode_casefold::UnicodeCaseFold;
+
+use std::path::PathBuf;
+
+/// A file produced by a backend. The path is relative to the directory the build writes to.
+#[derive(Debug, PartialEq)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Turns a resolved scenario into the files a lab tool needs to build it.
+pub trait Backend {
+    /// The name the backend is selected by with `build --backend`.
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn generate(&self, scenario: &ResolvedScenario) -> Result<Vec<OutputFile>, LabBuilderError>;
+}
+
+/// The backends available to `build`, looked up by name.
+pub struct BackendRegistry {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> BackendRegistry {
+        BackendRegistry {
+            backends: Vec::new(),
+        }
+    }
+
+    /// Registers a backend, replacing any already registered under the same name.
+    pub fn register(&mut self, backend: Box<dyn Backend>) -> &mut BackendRegistry {
+        self.backends
+            .retain(|existing| existing.name() != backend.name());
+        self.backends.push(backend);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Backend> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name() == name)
+            .map(|backend| backend.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.backends.iter().map(|backend| backend.name()).collect()
+    }
+}
+
+impl Default for BackendRegistry {
+    /// A registry containing every backend that ships with Lab Builder.
+    fn default() -> BackendRegistry {
+        let mut registry = BackendRegistry::new();
+        registry.register(Box::new(vagrant::VagrantBackend));
+        registry.register(Box::new(docker_compose::DockerComposeBackend));
+        registry.register(Box::new(inventory::InventoryBackend));
+        registry.register(Box::new(dns::DnsBackend));
+        registry
+    }
+}
+
+/// Turns names into unique host names, which may only contain lower case letters, digits,
+/// underscores, periods and dashes and must start with a letter or digit. Names that sanitise to
+/// the same host name, or to one of `reserved`, are told apart by a numeric suffix.
+pub(crate) fn unique_names<'a, I: IntoIterator<Item = &'a str>>(
+    names: I,
+    reserved: &[&str],
+) -> Vec<String> {
+    unique_names::unique_names(names, host_name, "-", reserved)
+}
+
+fn host_name(name: &str) -> String {
+    let mut host_name: String = name
+        .case_fold()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    if !host_name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        host_name.insert(0, 'x');
+    }
+    host_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBackend(&'static str);
+
+    impl Backend for TestBackend {
+        fn name(&self) -> &'static str {
+            "vagrant"
+        }
+
+        fn description(&self) -> &'static str {
+            self.0
+        }
+
+        fn generate(
+            &self,
+            _scenario: &ResolvedScenario,
+        ) -> Result<Vec<OutputFile>, LabBuilderError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn default_registry_should_contain_bundled_backends() {
+        let registry = BackendRegistry::default();
+
+        assert!(registry.names().contains(&"vagrant"));
+        assert!(registry.get("vagrant").is_some());
+        assert!(registry.get("docker-compose").is_some());
+        assert!(registry.get("inventory").is_some());
+        assert!(registry.get("dns").is_some());
+        assert!(registry.get("missing").is_none());
+    }
+
+    #[test]
+    fn registering_backend_should_replace_backend_with_same_name() {
+        let mut registry = BackendRegistry::default();
+        registry.register(Box::new(TestBackend("replacement")));
+
+        assert_eq!(
+            registry.names(),
+            vec!["docker-compose", "inventory", "dns", "vagrant"]
+        );
+        assert_eq!(
+            registry.get("vagrant").map(|backend| backend.description()),
+            Some("replacement")
+        );
+    }
+
+    #[test]
+    fn unique_names_should_sanitise_and_disambiguate_names() {
+        assert_eq!(
+            unique_names(
+                vec!["Web Server", "web-server", "_db", "Default"],
+                &["default"]
+            ),
+            vec!["web-server", "web-server-2", "x_db", "default-2"]
+        );
+    }
+}
@@ -0,0 +1,1122 @@
+use crate::backend::{self, Backend, OutputFile};
+use crate::error::LabBuilderError;
+use crate::firewall;
+use crate::forwarded_port::ForwardedPort;
+use crate::hardware::Hardware;
+use crate::indentation_aware_string_builder::{IndentationAwareStringBuilder, IndentationType};
+use crate::mac_address::MacAddress;
+use crate::name_resolution;
+use crate::network::{Network, NetworkType};
+use crate::provider::Provider;
+use crate::provisioner::{Provisioner, Script};
+use crate::resolved_scenario::{ResolvedNic, ResolvedScenario, ResolvedSystem};
+use crate::routing;
+use crate::ruby;
+
+use std::path::PathBuf;
+
+/// Generates a Vagrantfile for the scenario's provider.
+pub struct VagrantBackend;
+
+impl Backend for VagrantBackend {
+    fn name(&self) -> &'static str {
+        "vagrant"
+    }
+
+    fn description(&self) -> &'static str {
+        "Vagrantfile for VirtualBox or libvirt"
+    }
+
+    fn generate(&self, scenario: &ResolvedScenario) -> Result<Vec<OutputFile>, LabBuilderError> {
+        check_provider_addresses(scenario)?;
+
+        Ok(vec![OutputFile {
+            path: PathBuf::from("Vagrantfile"),
+            contents: to_vagrantfile(scenario),
+        }])
+    }
+}
+
+pub fn to_vagrantfile(scenario: &ResolvedScenario) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+    builder
+        .with_indentation_type(IndentationType::Spaces)
+        .with_tab_size(4);
+
+    builder.add("Vagrant.configure(\"2\") do |config|".to_string());
+    builder.increase_indentation();
+
+    let nat_network_names = virtualbox_nat_network_names(scenario);
+    for (net, nat_network_name) in nat_network_names.iter() {
+        let subnet = net.subnet.map(|subnet| subnet.trunc().to_string());
+        builder.add("config.trigger.before :up do |trigger|".to_string());
+        builder.increase_indentation();
+        builder.add(format!(
+            "trigger.name = {}",
+            ruby::string_literal(&format!("NAT network {}", net.name))
+        ));
+        builder.add(format!(
+            "trigger.run = {{inline: {}}}",
+            ruby::string_literal(&format!(
+                "VBoxManage natnetwork add --netname {} --network {} --enable --dhcp off",
+                nat_network_name,
+                subnet.unwrap_or_default()
+            ))
+        ));
+        builder.add("trigger.on_error = :continue".to_string());
+        builder.decrease_indentation();
+        builder.add("end".to_string());
+    }
+
+    let identifiers = ruby::unique_identifiers(scenario.systems.iter().map(|s| s.name.as_str()));
+
+    for (system, identifier) in scenario.systems.iter().zip(identifiers.iter()) {
+        builder.add(format!(
+            "config.vm.define {} do |{}|",
+            ruby::string_literal(&system.name),
+            identifier
+        ));
+        builder.increase_indentation();
+
+        builder.add(format!(
+            "{}.vm.box = {}",
+            identifier,
+            ruby::string_literal(&system.base_box)
+        ));
+
+        // VirtualBox gives every network line an adapter of its own, after the first one
+        // Vagrant keeps for itself. Adapters on NAT networks are moved onto them once created.
+        // Each NIC takes one line, so a dual stack NIC gets its IPv6 address when provisioned,
+        // and every adapter Vagrant creates is given the stable MAC address of its NIC.
+        let mut adapter = 1;
+        let mut nat_adapters = Vec::new();
+        for nic in system.nics.iter() {
+            let net = &nic.network;
+            let nat_network_name = nat_network_names
+                .iter()
+                .find(|(nat_net, _)| nat_net.name == net.name)
+                .map(|(_, name)| name.as_str());
+            match (net.network_type, &nic.lease) {
+                (NetworkType::Public, _) => {
+                    adapter += 1;
+                    builder.add(format!(
+                        r#"{}.vm.network "public_network"{}{}"#,
+                        identifier,
+                        public_network_options(scenario.provider, nic),
+                        mac_option(scenario.provider, nic.mac)
+                    ))
+                }
+                (_, Some(lease)) => {
+                    let address = match (lease.ipv4, net.subnet, lease.ipv6, net.ipv6_subnet) {
+                        (Some(ipv4), Some(subnet), _, _) => match net.network_type {
+                            NetworkType::Internal => format!(r#"ip: "{}""#, ipv4),
+                            _ => format!(r#"ip: "{}", netmask: "{}""#, ipv4, subnet.netmask()),
+                        },
+                        (None, _, Some(ipv6), Some(ipv6_subnet)) => {
+                            format!(r#"ip: "{}", netmask: "{}""#, ipv6, ipv6_subnet.prefix_len())
+                        }
+                        _ => continue,
+                    };
+                    adapter += 1;
+                    nat_adapters.extend(nat_network_name.map(|name| (adapter, name)));
+                    builder.add(format!(
+                        r#"{}.vm.network "private_network", {}{}{}"#,
+                        identifier,
+                        address,
+                        network_options(scenario.provider, net),
+                        mac_option(scenario.provider, nic.mac)
+                    ));
+                }
+                (_, None) => (),
+            }
+        }
+
+        for port in system.forwarded_ports.iter() {
+            builder.add(format!(
+                r#"{}.vm.network "forwarded_port", {}"#,
+                identifier,
+                forwarded_port_options(port)
+            ));
+        }
+
+        for folder in system.synced_folders.iter() {
+            let mut line = format!(
+                "{}.vm.synced_folder {}, {}",
+                identifier,
+                ruby::string_literal(&folder.host_path.to_string_lossy()),
+                ruby::string_literal(&folder.guest_path)
+            );
+            if let Some(folder_type) = folder.folder_type {
+                line.push_str(&format!(r#", type: "{}""#, folder_type.vagrant_name()));
+            }
+            builder.add(line);
+        }
+
+        match scenario.provider {
+            Provider::VirtualBox => {
+                add_virtualbox_hardware(&mut builder, identifier, &system.hardware, &nat_adapters)
+            }
+            Provider::Libvirt => add_libvirt_hardware(&mut builder, identifier, &system.hardware),
+        }
+
+        // Run on every boot, as DHCP clients rewrite the resolver config.
+        if let (Some(name_resolution), true) = (scenario.name_resolution, system.lab_names) {
+            builder.add(format!(
+                r#"{}.vm.provision "shell", name: "lab names", run: "always", inline: {}"#,
+                identifier,
+                ruby::string_literal(&name_resolution::provisioning_script(
+                    scenario,
+                    name_resolution
+                ))
+            ));
+        }
+
+        if let Some(ipv6_script) = dual_stack_script(system) {
+            builder.add(format!(
+                r#"{}.vm.provision "shell", name: "ipv6 addresses", run: "always", inline: {}"#,
+                identifier,
+                ruby::string_literal(&ipv6_script)
+            ));
+        }
+
+        if let Some(routing_script) = routing::provisioning_script(system) {
+            builder.add(format!(
+                r#"{}.vm.provision "shell", name: "routing", run: "always", inline: {}"#,
+                identifier,
+                ruby::string_literal(&routing_script)
+            ));
+        }
+
+        if let Some(firewall_script) = firewall::provisioning_script(scenario, system) {
+            builder.add(format!(
+                r#"{}.vm.provision "shell", name: "firewall", run: "always", inline: {}"#,
+                identifier,
+                ruby::string_literal(&firewall_script)
+            ));
+        }
+
+        for provisioner in system.provisioners.iter() {
+            builder.add(format!(
+                "{}.vm.provision {}",
+                identifier,
+                provisioner_options(provisioner)
+            ));
+        }
+
+        builder.decrease_indentation();
+        builder.add("end".to_string());
+    }
+
+    builder.decrease_indentation();
+    builder.add("end".to_string());
+
+    builder.build_string()
+}
+
+/// A shell script run on every boot, adding the IPv6 address of each dual stack NIC to the
+/// interface holding its IPv4 address. Returns nothing for systems without dual stack NICs.
+fn dual_stack_script(system: &ResolvedSystem) -> Option<String> {
+    let mut builder = IndentationAwareStringBuilder::new();
+    let mut dual_stack_nics = 0;
+
+    for nic in system.nics.iter() {
+        let net = &nic.network;
+        if let (Some(ipv4), Some(ipv6), Some(ipv6_subnet)) = (
+            nic.lease.and_then(|lease| lease.ipv4),
+            nic.lease.and_then(|lease| lease.ipv6),
+            net.ipv6_subnet,
+        ) {
+            dual_stack_nics += 1;
+            builder.add(format!(
+                r#"ip -6 addr replace {}/{} dev "$(ip -o -4 addr show to {}/32 | awk '{{print $2}}')""#,
+                ipv6,
+                ipv6_subnet.prefix_len(),
+                ipv4
+            ));
+        }
+    }
+
+    match dual_stack_nics {
+        0 => None,
+        _ => Some(builder.build_string()),
+    }
+}
+
+/// Extra disks use Vagrant's disk feature, and the rest of the hardware is set in a VirtualBox
+/// provider block, which is left out when the box's defaults are kept. The block also attaches
+/// the given adapters to their NAT networks.
+fn add_virtualbox_hardware(
+    builder: &mut IndentationAwareStringBuilder,
+    identifier: &str,
+    hardware: &Hardware,
+    nat_adapters: &[(usize, &str)],
+) {
+    for (index, disk) in hardware.disks.iter().enumerate() {
+        builder.add(format!(
+            r#"{}.vm.disk :disk, name: "disk{}", size: "{}""#,
+            identifier,
+            index + 1,
+            disk.size.format_with("MB", "GB")
+        ));
+    }
+
+    if hardware.cpus.is_none()
+        && hardware.memory.is_none()
+        && hardware.gui.is_none()
+        && nat_adapters.is_empty()
+    {
+        return;
+    }
+
+    builder.add(format!(
+        "{}.vm.provider :virtualbox do |virtualbox|",
+        identifier
+    ));
+    builder.increase_indentation();
+    if let Some(cpus) = hardware.cpus {
+        builder.add(format!("virtualbox.cpus = {}", cpus));
+    }
+    if let Some(memory) = hardware.memory {
+        builder.add(format!("virtualbox.memory = {}", memory.mebibytes));
+    }
+    if let Some(gui) = hardware.gui {
+        builder.add(format!("virtualbox.gui = {}", gui));
+    }
+    for (adapter, nat_network_name) in nat_adapters.iter() {
+        builder.add(format!(
+            r#"virtualbox.customize ["modifyvm", :id, "--nic{0}", "natnetwork", "--nat-network{0}", "{1}"]"#,
+            adapter, nat_network_name
+        ));
+    }
+    builder.decrease_indentation();
+    builder.add("end".to_string());
+}
+
+/// Libvirt machines always get a provider block, as they need KVM. Machines with a GUI get a
+/// SPICE display, while headless ones keep vagrant-libvirt's default VNC display.
+fn add_libvirt_hardware(
+    builder: &mut IndentationAwareStringBuilder,
+    identifier: &str,
+    hardware: &Hardware,
+) {
+    builder.add(format!("{}.vm.provider :libvirt do |libvirt|", identifier));
+    builder.increase_indentation();
+    builder.add(r#"libvirt.driver = "kvm""#.to_string());
+    if let Some(cpus) = hardware.cpus {
+        builder.add(format!("libvirt.cpus = {}", cpus));
+    }
+    if let Some(memory) = hardware.memory {
+        builder.add(format!("libvirt.memory = {}", memory.mebibytes));
+    }
+    for disk in hardware.disks.iter() {
+        let size = disk.size.format_with("M", "G");
+        match disk.bus {
+            Some(bus) => builder.add(format!(
+                r#"libvirt.storage :file, size: "{}", bus: "{}""#,
+                size,
+                bus.libvirt_name()
+            )),
+            None => builder.add(format!(r#"libvirt.storage :file, size: "{}""#, size)),
+        }
+    }
+    if hardware.gui == Some(true) {
+        builder.add(r#"libvirt.graphics_type = "spice""#.to_string());
+        builder.add(r#"libvirt.video_type = "qxl""#.to_string());
+    }
+    builder.decrease_indentation();
+    builder.add("end".to_string());
+}
+
+/// Options of a forwarded port. Vagrant's auto correction is turned off, as it would silently move
+/// ports away from the host ports the scenario promises.
+fn forwarded_port_options(port: &ForwardedPort) -> String {
+    let mut options = format!(
+        "guest: {}, host: {}, protocol: \"{}\"",
+        port.guest, port.host, port.protocol
+    );
+    if let Some(host_ip) = port.host_ip {
+        options.push_str(&format!(r#", host_ip: "{}""#, host_ip));
+    }
+    options.push_str(", auto_correct: false");
+    options
+}
+
+/// The provisioner type and options of a `vm.provision` line. PowerShell scripts run with the
+/// shell provisioner, which uses PowerShell on Windows guests.
+fn provisioner_options(provisioner: &Provisioner) -> String {
+    let script_options = |script: &Script| match script {
+        Script::Inline(inline) => format!("inline: {}", ruby::string_literal(inline)),
+        Script::Path(path) => format!("path: {}", ruby::string_literal(&path.to_string_lossy())),
+    };
+
+    match provisioner {
+        Provisioner::Shell(script) => format!(r#""shell", {}"#, script_options(script)),
+        Provisioner::PowerShell(script) => format!(
+            r#""shell", {}, powershell_args: "-ExecutionPolicy Bypass""#,
+            script_options(script)
+        ),
+        Provisioner::Ansible { playbook } => format!(
+            r#""ansible", playbook: {}"#,
+            ruby::string_literal(&playbook.to_string_lossy())
+        ),
+        Provisioner::File {
+            source,
+            destination,
+        } => format!(
+            r#""file", source: {}, destination: {}"#,
+            ruby::string_literal(&source.to_string_lossy()),
+            ruby::string_literal(destination)
+        ),
+    }
+}
+
+/// Options placing a NIC on an addressed network, starting with a comma. VirtualBox NICs on NAT
+/// networks start out on an internal network of the same name, as Vagrant can't create NAT
+/// networks itself. Addresses are assigned statically, so libvirt's DHCP server is disabled.
+fn network_options(provider: Provider, net: &Network) -> String {
+    match (provider, net.network_type) {
+        (Provider::VirtualBox, NetworkType::HostOnly) => "".to_string(),
+        (Provider::VirtualBox, _) => {
+            format!(", virtualbox__intnet: {}", ruby::string_literal(&net.name))
+        }
+        (Provider::Libvirt, network_type) => {
+            let mut options = format!(
+                r#", libvirt__network_name: {}, libvirt__dhcp_enabled: false, libvirt__forward_mode: "{}""#,
+                ruby::string_literal(&net.name),
+                match network_type {
+                    NetworkType::Nat => "nat",
+                    _ => "none",
+                }
+            );
+            if let Some(host_ip) = net.host_ip.or(match network_type {
+                NetworkType::Nat => net.gateway,
+                _ => None,
+            }) {
+                options.push_str(&format!(r#", libvirt__host_ip: "{}""#, host_ip));
+            }
+            options
+        }
+    }
+}
+
+/// Options choosing the interface a public network is bridged to and the NIC's static IP,
+/// starting with a comma. Vagrant asks for the interface when none is given. libvirt can only be
+/// given one interface, so the first candidate is used.
+fn public_network_options(provider: Provider, nic: &ResolvedNic) -> String {
+    let mut options = String::new();
+
+    match (provider, nic.network.bridge.as_slice()) {
+        (_, []) => (),
+        (Provider::VirtualBox, [interface]) => {
+            options.push_str(&format!(", bridge: {}", ruby::string_literal(interface)))
+        }
+        (Provider::VirtualBox, interfaces) => {
+            let interfaces: Vec<String> = interfaces
+                .iter()
+                .map(|interface| ruby::string_literal(interface))
+                .collect();
+            options.push_str(&format!(", bridge: [{}]", interfaces.join(", ")))
+        }
+        (Provider::Libvirt, [interface, ..]) => options.push_str(&format!(
+            r#", dev: {}, mode: "bridge", type: "direct""#,
+            ruby::string_literal(interface)
+        )),
+    }
+
+    if let Some(static_ip) = nic.static_ip {
+        options.push_str(&format!(r#", ip: "{}""#, static_ip));
+    }
+    if let Some(netmask) = nic.netmask {
+        options.push_str(&format!(r#", netmask: "{}""#, netmask));
+    }
+
+    options
+}
+
+/// The option setting a NIC's MAC address, starting with a comma. VirtualBox wants the address
+/// as bare hex digits, while libvirt takes the usual colon separated form.
+fn mac_option(provider: Provider, mac: MacAddress) -> String {
+    match provider {
+        Provider::VirtualBox => format!(r#", mac: "{}""#, mac.to_hex()),
+        Provider::Libvirt => format!(r#", mac: "{}""#, mac),
+    }
+}
+
+/// NAT networks VirtualBox machines are attached to, along with the name each is created under.
+fn virtualbox_nat_network_names(scenario: &ResolvedScenario) -> Vec<(&Network, String)> {
+    if scenario.provider != Provider::VirtualBox {
+        return Vec::new();
+    }
+
+    let nat_networks: Vec<&Network> = scenario
+        .networks
+        .iter()
+        .map(|net| net.as_ref())
+        .filter(|net| net.network_type == NetworkType::Nat)
+        .collect();
+    let names = backend::unique_names(nat_networks.iter().map(|net| net.name.as_str()), &[]);
+
+    nat_networks.into_iter().zip(names).collect()
+}
+
+/// VirtualBox always gives the host the first address of a host-only network, and uses it as
+/// the gateway of NAT networks, so other addresses can't be honoured.
+fn check_provider_addresses(scenario: &ResolvedScenario) -> Result<(), LabBuilderError> {
+    if scenario.provider != Provider::VirtualBox {
+        return Ok(());
+    }
+
+    for (index, net) in scenario.networks.iter().enumerate() {
+        let first_host = match net.subnet.and_then(|subnet| subnet.hosts().next()) {
+            Some(first_host) => first_host,
+            None => continue,
+        };
+        let (field, addr, message) = match net.network_type {
+            NetworkType::HostOnly => (
+                "host_ip",
+                net.host_ip,
+                "VirtualBox always gives the host the first address of a host-only network.",
+            ),
+            NetworkType::Nat => (
+                "gateway",
+                net.gateway,
+                "VirtualBox NAT networks always use the first address of the subnet as their gateway.",
+            ),
+            NetworkType::Internal | NetworkType::Public => continue,
+        };
+        if addr.is_some_and(|addr| addr != first_host) {
+            return Err(LabBuilderError::invalid_value(
+                &format!("networks[{}].{}", index, field),
+                message,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    #[test]
+    fn vagrantfile_output_for_simple_scenario_works(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN"]
+            base_box = "Windows 10"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Desktop" do |desktop|
+        desktop.vm.box = "Windows 10"
+        desktop.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "62095658F8BE"
+    end
+    config.vm.define "Server" do |server|
+        server.vm.box = "Debian"
+        server.vm.network "private_network", ip: "192.168.0.2", virtualbox__intnet: "LAN", mac: "EAFCB78677D1"
+    end
+end"#
+            .to_string();
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_for_dual_stack_scenario_should_use_one_adapter_per_nic(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN", "Lab"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+            ipv6_subnet = "fd00:1::/64"
+
+            [[networks]]
+            name = "Lab"
+            type = "Internal"
+            ipv6_subnet = "fd00:2::/64"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Server" do |server|
+        server.vm.box = "Debian"
+        server.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "EAFCB78677D1"
+        server.vm.network "private_network", ip: "fd00:2::1", netmask: "64", virtualbox__intnet: "Lab", mac: "824017BEA506"
+        server.vm.provision "shell", name: "ipv6 addresses", run: "always", inline: "ip -6 addr replace fd00:1::1/64 dev \"$(ip -o -4 addr show to 192.168.0.1/32 | awk '{print $2}')\""
+    end
+end"#
+            .to_string();
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_give_every_adapter_its_nic_mac_address(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN", "Lab", "Office"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+            ipv6_subnet = "fd00:1::/64"
+
+            [[networks]]
+            name = "Lab"
+            type = "Internal"
+            ipv6_subnet = "fd00:2::/64"
+
+            [[networks]]
+            name = "Office"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        for &provider in &[Provider::VirtualBox, Provider::Libvirt] {
+            scenario.provider = provider;
+            let vagrantfile = to_vagrantfile(&scenario);
+            let adapters: Vec<&str> = vagrantfile
+                .lines()
+                .filter(|line| line.contains(".vm.network "))
+                .collect();
+
+            assert_eq!(adapters.len(), scenario.systems[0].nics.len());
+            for (adapter, nic) in adapters.iter().zip(scenario.systems[0].nics.iter()) {
+                assert!(adapter.ends_with(&mac_option(provider, nic.mac)));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_sanitise_identifiers_and_escape_names(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r##"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Web-1"
+            networks = ["Internet"]
+            base_box = "Debian"
+
+            [[systems]]
+            name = "web_1"
+            networks = ["LAN"]
+            base_box = "Debian \"#{`id`}\""
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+
+            [[networks]]
+            name = "Internet"
+            type = "Public"
+        "##
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r##"Vagrant.configure("2") do |config|
+    config.vm.define "Web-1" do |web_1|
+        web_1.vm.box = "Debian"
+        web_1.vm.network "public_network", mac: "86A8EE045A15"
+    end
+    config.vm.define "web_1" do |web_1_2|
+        web_1_2.vm.box = "Debian \"\#{`id`}\""
+        web_1_2.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "5A8A19772DD7"
+    end
+end"##
+            .to_string();
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_for_libvirt_provider_uses_isolated_networks(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            provider = "Libvirt"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "generic/debian10"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Server" do |server|
+        server.vm.box = "generic/debian10"
+        server.vm.network "private_network", ip: "192.168.0.1", libvirt__network_name: "LAN", libvirt__dhcp_enabled: false, libvirt__forward_mode: "none", mac: "ea:fc:b7:86:77:d1"
+        server.vm.provider :libvirt do |libvirt|
+            libvirt.driver = "kvm"
+        end
+    end
+end"#
+            .to_string();
+
+        assert_eq!(scenario.provider, Provider::Libvirt);
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_provision_hosts_fragment_on_systems_opting_in(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            name_resolution = "Hosts"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+            lab_names = true
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN"]
+            base_box = "Windows 10"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Server" do |server|
+        server.vm.box = "Debian"
+        server.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "EAFCB78677D1"
+        server.vm.provision "shell", name: "lab names", run: "always", inline: "sed -i '/^\# BEGIN LabBuilder$/,/^\# END LabBuilder$/d' /etc/hosts\ncat >> /etc/hosts <<'LABBUILDER'\n\# BEGIN LabBuilder\n192.168.0.1 server.lan.lab\n192.168.0.2 desktop.lan.lab\n\# END LabBuilder\nLABBUILDER"
+    end
+    config.vm.define "Desktop" do |desktop|
+        desktop.vm.box = "Windows 10"
+        desktop.vm.network "private_network", ip: "192.168.0.2", virtualbox__intnet: "LAN", mac: "62095658F8BE"
+    end
+end"#
+            .to_string();
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_set_hardware_for_each_provider(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "DC"
+            networks = ["LAN"]
+            base_box = "gusztavvargadr/windows-server"
+            cpus = 2
+            memory = "4GB"
+            gui = true
+            disks = [{ size = "20GB" }, { size = 512, bus = "Scsi" }]
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "DC" do |dc|
+        dc.vm.box = "gusztavvargadr/windows-server"
+        dc.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "96D6E50CC057"
+        dc.vm.disk :disk, name: "disk1", size: "20GB"
+        dc.vm.disk :disk, name: "disk2", size: "512MB"
+        dc.vm.provider :virtualbox do |virtualbox|
+            virtualbox.cpus = 2
+            virtualbox.memory = 4096
+            virtualbox.gui = true
+        end
+    end
+end"#;
+        assert_eq!(to_vagrantfile(&scenario), expected);
+
+        scenario.provider = Provider::Libvirt;
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "DC" do |dc|
+        dc.vm.box = "gusztavvargadr/windows-server"
+        dc.vm.network "private_network", ip: "192.168.0.1", libvirt__network_name: "LAN", libvirt__dhcp_enabled: false, libvirt__forward_mode: "none", mac: "96:d6:e5:0c:c0:57"
+        dc.vm.provider :libvirt do |libvirt|
+            libvirt.driver = "kvm"
+            libvirt.cpus = 2
+            libvirt.memory = 4096
+            libvirt.storage :file, size: "20G"
+            libvirt.storage :file, size: "512M", bus: "scsi"
+            libvirt.graphics_type = "spice"
+            libvirt.video_type = "qxl"
+        end
+    end
+end"#;
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_provision_systems_in_order(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r##"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[systems.provisioners]]
+            type = "Shell"
+            inline = "echo \"#{hostname}\""
+
+            [[systems.provisioners]]
+            type = "File"
+            source = "files/motd"
+            destination = "/etc/motd"
+
+            [[systems.provisioners]]
+            type = "Ansible"
+            playbook = "site.yml"
+
+            [[systems.provisioners]]
+            type = "PowerShell"
+            path = "scripts/setup.ps1"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "##
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r##"Vagrant.configure("2") do |config|
+    config.vm.define "Server" do |server|
+        server.vm.box = "Debian"
+        server.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "EAFCB78677D1"
+        server.vm.provision "shell", inline: "echo \"\#{hostname}\""
+        server.vm.provision "file", source: "files/motd", destination: "/etc/motd"
+        server.vm.provision "ansible", playbook: "site.yml"
+        server.vm.provision "shell", path: "scripts/setup.ps1", powershell_args: "-ExecutionPolicy Bypass"
+    end
+end"##;
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_forward_ports_and_sync_folders(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Web"
+            networks = ["LAN"]
+            base_box = "Debian"
+            forwarded_ports = [
+                { guest = 80, host = 8080, host_ip = "127.0.0.1" },
+                { guest = 53, host = 5353, protocol = "Udp" },
+            ]
+            synced_folders = [
+                { host_path = "exercises", guest_path = "/srv/exercises", type = "Rsync" },
+                { host_path = "notes", guest_path = "/srv/notes" },
+            ]
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Web" do |web|
+        web.vm.box = "Debian"
+        web.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN", mac: "2ABB7FA458D8"
+        web.vm.network "forwarded_port", guest: 80, host: 8080, protocol: "tcp", host_ip: "127.0.0.1", auto_correct: false
+        web.vm.network "forwarded_port", guest: 53, host: 5353, protocol: "udp", auto_correct: false
+        web.vm.synced_folder "exercises", "/srv/exercises", type: "rsync"
+        web.vm.synced_folder "notes", "/srv/notes"
+    end
+end"#;
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_configure_routing_on_every_boot(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Router"
+            networks = ["LAN", "DMZ"]
+            base_box = "Debian"
+            router = true
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "10.0.1.0/24"
+
+            [[networks]]
+            name = "DMZ"
+            type = "Internal"
+            subnet = "10.0.2.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.vm.define "Router" do |router|
+        router.vm.box = "Debian"
+        router.vm.network "private_network", ip: "10.0.1.1", virtualbox__intnet: "LAN", mac: "4A00763CDB05"
+        router.vm.network "private_network", ip: "10.0.2.1", virtualbox__intnet: "DMZ", mac: "9A5C18DBCD06"
+        router.vm.provision "shell", name: "routing", run: "always", inline: "sysctl -w net.ipv4.ip_forward=1\nsysctl -w net.ipv6.conf.all.forwarding=1"
+    end
+    config.vm.define "Desktop" do |desktop|
+        desktop.vm.box = "Debian"
+        desktop.vm.network "private_network", ip: "10.0.1.2", virtualbox__intnet: "LAN", mac: "62095658F8BE"
+        desktop.vm.provision "shell", name: "routing", run: "always", inline: "ip route replace 10.0.2.0/24 via 10.0.1.1"
+    end
+end"#;
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_attach_host_only_and_nat_networks(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Web"
+            networks = ["Host", "Outside"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "Host"
+            type = "HostOnly"
+            subnet = "192.168.56.0/24"
+
+            [[networks]]
+            name = "Outside"
+            type = "Nat"
+            subnet = "10.0.5.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let expected = r#"Vagrant.configure("2") do |config|
+    config.trigger.before :up do |trigger|
+        trigger.name = "NAT network Outside"
+        trigger.run = {inline: "VBoxManage natnetwork add --netname outside --network 10.0.5.0/24 --enable --dhcp off"}
+        trigger.on_error = :continue
+    end
+    config.vm.define "Web" do |web|
+        web.vm.box = "Debian"
+        web.vm.network "private_network", ip: "192.168.56.2", netmask: "255.255.255.0", mac: "2ABB7FA458D8"
+        web.vm.network "private_network", ip: "10.0.5.2", netmask: "255.255.255.0", virtualbox__intnet: "Outside", mac: "BA86B42B31A7"
+        web.vm.provider :virtualbox do |virtualbox|
+            virtualbox.customize ["modifyvm", :id, "--nic3", "natnetwork", "--nat-network3", "outside"]
+        end
+    end
+end"#;
+
+        assert_eq!(to_vagrantfile(&scenario), expected);
+
+        scenario.provider = Provider::Libvirt;
+        let vagrantfile = to_vagrantfile(&scenario);
+        assert!(vagrantfile.contains(r#"web.vm.network "private_network", ip: "192.168.56.2", netmask: "255.255.255.0", libvirt__network_name: "Host", libvirt__dhcp_enabled: false, libvirt__forward_mode: "none", libvirt__host_ip: "192.168.56.1""#));
+        assert!(vagrantfile.contains(r#"web.vm.network "private_network", ip: "10.0.5.2", netmask: "255.255.255.0", libvirt__network_name: "Outside", libvirt__dhcp_enabled: false, libvirt__forward_mode: "nat", libvirt__host_ip: "10.0.5.1""#));
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_bridge_public_networks_without_prompting(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Attacker"
+            networks = [
+                { name = "Office", ip = "192.0.2.10", netmask = "255.255.255.0" },
+                { name = "Wifi" },
+            ]
+            base_box = "Kali"
+
+            [[networks]]
+            name = "Office"
+            type = "Public"
+            bridge = ["eth0", "eno1"]
+
+            [[networks]]
+            name = "Wifi"
+            type = "Public"
+            bridge = "wlan0"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        let vagrantfile = to_vagrantfile(&scenario);
+        assert!(vagrantfile.contains(r#"attacker.vm.network "public_network", bridge: ["eth0", "eno1"], ip: "192.0.2.10", netmask: "255.255.255.0""#));
+        assert!(vagrantfile.contains(r#"attacker.vm.network "public_network", bridge: "wlan0""#));
+
+        scenario.provider = Provider::Libvirt;
+        let vagrantfile = to_vagrantfile(&scenario);
+        assert!(vagrantfile.contains(r#"attacker.vm.network "public_network", dev: "eth0", mode: "bridge", type: "direct", ip: "192.0.2.10", netmask: "255.255.255.0""#));
+        Ok(())
+    }
+
+    #[test]
+    fn generating_virtualbox_host_only_network_with_other_host_ip_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Web"
+            networks = ["Host"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "Host"
+            type = "HostOnly"
+            subnet = "192.168.56.0/24"
+            host_ip = "192.168.56.254"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        assert_eq!(
+            VagrantBackend.generate(&scenario).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "networks[0].host_ip",
+                "VirtualBox always gives the host the first address of a host-only network."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn vagrantfile_output_should_configure_firewall_on_routers(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            firewall_policy = "Drop"
+
+            [[systems]]
+            name = "Router"
+            networks = ["LAN"]
+            base_box = "Debian"
+            router = true
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "10.0.1.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&input)?.resolve()?;
+
+        assert!(to_vagrantfile(&scenario).contains(
+            r#"router.vm.provision "shell", name: "firewall", run: "always", inline: "nft -f - <<'LABBUILDER'\ntable inet labbuilder\n"#
+        ));
+        Ok(())
+    }
+}
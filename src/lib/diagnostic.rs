@@ -0,0 +1,101 @@
+use crate::error::LabBuilderError;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: LabBuilderError,
+}
+
+impl Diagnostic {
+    pub fn error(error: LabBuilderError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            error,
+        }
+    }
+
+    pub fn warning(error: LabBuilderError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            error,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_source(self, source: &str) -> Diagnostic {
+        Diagnostic {
+            severity: self.severity,
+            error: self.error.with_source(source),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.error),
+            Severity::Warning => write!(f, "warning: {}", self.error),
+        }
+    }
+}
+
+/// Records the error of a failed parse step so the caller can carry on with the rest of the
+/// scenario, letting a single validation pass report every problem it contains.
+pub fn collect<T>(
+    result: Result<T, LabBuilderError>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(error) => {
+            diagnostics.push(Diagnostic::error(error));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_should_record_errors_and_keep_values() {
+        let mut diagnostics = Vec::new();
+
+        assert_eq!(collect(Ok(42), &mut diagnostics), Some(42));
+        assert_eq!(
+            collect::<u32>(
+                Err(LabBuilderError::missing_field("name")),
+                &mut diagnostics
+            ),
+            None
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(LabBuilderError::missing_field("name"))]
+        );
+    }
+
+    #[test]
+    fn diagnostics_should_display_severity() {
+        let warning = Diagnostic::warning(LabBuilderError::missing_field("name"));
+
+        assert_eq!(
+            warning.to_string(),
+            r#"warning: Missing required field "name""#
+        );
+        assert!(!warning.is_error());
+    }
+}
@@ -0,0 +1,389 @@
+use crate::error::{LabBuilderError, Location};
+use crate::indentation_aware_string_builder::{IndentationAwareStringBuilder, IndentationType};
+use crate::network::NetworkType;
+use crate::resolved_scenario::ResolvedScenario;
+
+/// The topology of a scenario as a graph, with networks and systems as nodes and an edge for
+/// every NIC.
+#[derive(Debug, PartialEq)]
+pub struct Diagram {
+    pub scenario: String,
+    pub networks: Vec<NetworkNode>,
+    pub systems: Vec<SystemNode>,
+    pub links: Vec<Link>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NetworkNode {
+    pub name: String,
+    pub public: bool,
+    pub subnets: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SystemNode {
+    pub name: String,
+    pub base_box: String,
+    /// Whether the system is marked as a router or is attached to more than one network, so it
+    /// can route between them.
+    pub router: bool,
+}
+
+/// A NIC, joining the system and network at the given indices.
+#[derive(Debug, PartialEq)]
+pub struct Link {
+    pub system: usize,
+    pub network: usize,
+    pub addresses: Vec<String>,
+}
+
+impl Diagram {
+    pub fn from_scenario(scenario: &ResolvedScenario) -> Result<Diagram, LabBuilderError> {
+        let networks = scenario
+            .networks
+            .iter()
+            .map(|net| NetworkNode {
+                name: net.name.to_string(),
+                public: net.network_type == NetworkType::Public,
+                subnets: net
+                    .subnet
+                    .map(|subnet| subnet.trunc().to_string())
+                    .into_iter()
+                    .chain(net.ipv6_subnet.map(|subnet| subnet.trunc().to_string()))
+                    .collect(),
+            })
+            .collect();
+
+        let mut systems = Vec::new();
+        let mut links = Vec::new();
+        for (system_index, system) in scenario.systems.iter().enumerate() {
+            let mut attached_networks = Vec::new();
+            for (nic_index, nic) in system.nics.iter().enumerate() {
+                let network_index = scenario
+                    .networks
+                    .iter()
+                    .position(|net| net.name == nic.network.name)
+                    .ok_or_else(|| LabBuilderError::UnknownNetworkReference {
+                        location: Location::new(&format!(
+                            "systems[{}].networks[{}]",
+                            system_index, nic_index
+                        )),
+                        system: system.name.to_string(),
+                        network: nic.network.name.to_string(),
+                    })?;
+                if !attached_networks.contains(&network_index) {
+                    attached_networks.push(network_index);
+                }
+
+                links.push(Link {
+                    system: system_index,
+                    network: network_index,
+                    addresses: nic
+                        .lease
+                        .iter()
+                        .flat_map(|lease| {
+                            lease
+                                .ipv4
+                                .map(|addr| addr.to_string())
+                                .into_iter()
+                                .chain(lease.ipv6.map(|addr| addr.to_string()))
+                        })
+                        .collect(),
+                });
+            }
+
+            systems.push(SystemNode {
+                name: system.name.to_string(),
+                base_box: system.base_box.to_string(),
+                router: system.router || attached_networks.len() > 1,
+            });
+        }
+
+        Ok(Diagram {
+            scenario: scenario.name.to_string(),
+            networks,
+            systems,
+            links,
+        })
+    }
+
+    /// Renders the diagram as a Graphviz graph. Networks are ellipses, dashed when public, and
+    /// systems are boxes, except routers which are filled octagons.
+    pub fn to_dot(&self) -> String {
+        let mut builder = IndentationAwareStringBuilder::new();
+        builder
+            .with_indentation_type(IndentationType::Spaces)
+            .with_tab_size(4);
+
+        builder.add(format!("graph {} {{", dot_string(&self.scenario)));
+        builder.increase_indentation();
+        builder.add(format!("label = {};", dot_string(&self.scenario)));
+
+        for (index, net) in self.networks.iter().enumerate() {
+            let label: Vec<&str> = std::iter::once(net.name.as_str())
+                .chain(net.subnets.iter().map(String::as_str))
+                .collect();
+            let style = match net.public {
+                true => ", style = dashed",
+                false => "",
+            };
+            builder.add(format!(
+                "net{} [shape = ellipse{}, label = {}];",
+                index,
+                style,
+                dot_string(&label.join("\n"))
+            ));
+        }
+
+        for (index, system) in self.systems.iter().enumerate() {
+            let shape = match system.router {
+                true => "octagon, style = filled, fillcolor = lightgrey",
+                false => "box",
+            };
+            builder.add(format!(
+                "sys{} [shape = {}, label = {}];",
+                index,
+                shape,
+                dot_string(&format!("{}\n{}", system.name, system.base_box))
+            ));
+        }
+
+        for link in self.links.iter() {
+            match link.addresses.is_empty() {
+                true => builder.add(format!("sys{} -- net{};", link.system, link.network)),
+                false => builder.add(format!(
+                    "sys{} -- net{} [label = {}];",
+                    link.system,
+                    link.network,
+                    dot_string(&link.addresses.join("\n"))
+                )),
+            }
+        }
+
+        builder.decrease_indentation();
+        builder.add("}".to_string());
+
+        builder.build_string()
+    }
+
+    /// Renders the diagram as a Mermaid flowchart. Networks are stadiums, public ones with a
+    /// dashed border, and systems are rectangles, except routers which are hexagons.
+    pub fn to_mermaid(&self) -> String {
+        let mut builder = IndentationAwareStringBuilder::new();
+        builder
+            .with_indentation_type(IndentationType::Spaces)
+            .with_tab_size(4);
+
+        builder.add("graph LR".to_string());
+        builder.increase_indentation();
+
+        for (index, net) in self.networks.iter().enumerate() {
+            let label: Vec<String> = std::iter::once(&net.name)
+                .chain(net.subnets.iter())
+                .map(|line| mermaid_text(line))
+                .collect();
+            builder.add(format!("net{}([\"{}\"])", index, label.join("<br/>")));
+        }
+
+        for (index, system) in self.systems.iter().enumerate() {
+            let label = format!(
+                "{}<br/>{}",
+                mermaid_text(&system.name),
+                mermaid_text(&system.base_box)
+            );
+            match system.router {
+                true => builder.add(format!("sys{}{{{{\"{}\"}}}}", index, label)),
+                false => builder.add(format!("sys{}[\"{}\"]", index, label)),
+            }
+        }
+
+        for link in self.links.iter() {
+            match link.addresses.is_empty() {
+                true => builder.add(format!("sys{} --- net{}", link.system, link.network)),
+                false => builder.add(format!(
+                    "sys{} ---|\"{}\"| net{}",
+                    link.system,
+                    link.addresses.join("<br/>"),
+                    link.network
+                )),
+            }
+        }
+
+        let public_networks: Vec<String> = (0..self.networks.len())
+            .filter(|&index| self.networks[index].public)
+            .map(|index| format!("net{}", index))
+            .collect();
+        if !public_networks.is_empty() {
+            builder.add("classDef public stroke-dasharray: 5 5".to_string());
+            builder.add(format!("class {} public", public_networks.join(",")));
+        }
+
+        let routers: Vec<String> = (0..self.systems.len())
+            .filter(|&index| self.systems[index].router)
+            .map(|index| format!("sys{}", index))
+            .collect();
+        if !routers.is_empty() {
+            builder.add("classDef router fill:#d3d3d3".to_string());
+            builder.add(format!("class {} router", routers.join(",")));
+        }
+
+        builder.decrease_indentation();
+
+        builder.build_string()
+    }
+}
+
+/// Quotes a value as a DOT string. Newlines become `\n` line breaks in labels.
+fn dot_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '"' => quoted.push_str(r#"\""#),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => (),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes text for use inside a quoted Mermaid label using Mermaid's entity codes, so it can't
+/// end the label or be read as markup.
+fn mermaid_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    fn diagram() -> Result<Diagram, std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Gateway"
+            networks = ["LAN", "WAN"]
+            base_box = "VyOS"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/64"
+
+            [[networks]]
+            name = "WAN"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        Ok(Diagram::from_scenario(
+            &Scenario::from_toml(&input)?.resolve()?,
+        )?)
+    }
+
+    #[test]
+    fn dot_output_for_simple_scenario_works() -> Result<(), std::boxed::Box<dyn std::error::Error>>
+    {
+        let expected = r#"graph "Test scenario" {
+    label = "Test scenario";
+    net0 [shape = ellipse, label = "LAN\n192.168.0.0/24\nfd00::/64"];
+    net1 [shape = ellipse, style = dashed, label = "WAN"];
+    sys0 [shape = octagon, style = filled, fillcolor = lightgrey, label = "Gateway\nVyOS"];
+    sys1 [shape = box, label = "Server\nDebian"];
+    sys0 -- net0 [label = "192.168.0.1\nfd00::1"];
+    sys0 -- net1;
+    sys1 -- net0 [label = "192.168.0.2\nfd00::2"];
+}"#;
+
+        assert_eq!(diagram()?.to_dot(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn mermaid_output_for_simple_scenario_works(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let expected = r#"graph LR
+    net0(["LAN<br/>192.168.0.0/24<br/>fd00::/64"])
+    net1(["WAN"])
+    sys0{{"Gateway<br/>VyOS"}}
+    sys1["Server<br/>Debian"]
+    sys0 ---|"192.168.0.1<br/>fd00::1"| net0
+    sys0 --- net1
+    sys1 ---|"192.168.0.2<br/>fd00::2"| net0
+    classDef public stroke-dasharray: 5 5
+    class net1 public
+    classDef router fill:#d3d3d3
+    class sys0 router"#;
+
+        assert_eq!(diagram()?.to_mermaid(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn diagram_for_nic_on_missing_network_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?.resolve()?;
+        scenario.networks.clear();
+
+        assert_eq!(
+            Diagram::from_scenario(&scenario).unwrap_err(),
+            LabBuilderError::UnknownNetworkReference {
+                location: Location::new("systems[0].networks[0]"),
+                system: "Server".to_string(),
+                network: "LAN".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn labels_should_be_escaped() {
+        assert_eq!(dot_string(r#"Evil" \ name"#), r#""Evil\" \\ name""#);
+        assert_eq!(
+            mermaid_text(r#"Evil"] --> <b>#1</b>"#),
+            "Evil#quot;] --#gt; #lt;b#gt;#35;1#lt;/b#gt;"
+        );
+    }
+}
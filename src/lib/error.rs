@@ -0,0 +1,486 @@
+use serde::de;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    pub line_col: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LabBuilderError {
+    Syntax {
+        location: Location,
+        message: String,
+    },
+    MissingField {
+        location: Location,
+    },
+    WrongType {
+        location: Location,
+        expected: String,
+    },
+    UnknownField {
+        location: Location,
+        suggestion: Option<String>,
+    },
+    InvalidValue {
+        location: Location,
+        message: String,
+    },
+    DuplicateName {
+        location: Location,
+        kind: &'static str,
+        name: String,
+    },
+    UnknownNetworkReference {
+        location: Location,
+        system: String,
+        network: String,
+    },
+    SubnetExhausted {
+        location: Location,
+        network: String,
+    },
+    HashedAddressTaken {
+        location: Location,
+        network: String,
+        address: String,
+    },
+    UnusedNetwork {
+        location: Location,
+        network: String,
+    },
+    HostPortInUse {
+        location: Location,
+        port: u16,
+        protocol: String,
+        system: String,
+    },
+    NoRoute {
+        location: Location,
+        system: String,
+        network: String,
+    },
+    AmbiguousRoute {
+        location: Location,
+        system: String,
+        network: String,
+        routers: Vec<String>,
+    },
+    MissingBridgeInterface {
+        location: Location,
+        interfaces: Vec<String>,
+    },
+    DuplicateMacAddress {
+        location: Location,
+        mac: String,
+        system: String,
+    },
+}
+
+impl Location {
+    pub fn new(path: &str) -> Location {
+        Location {
+            path: path.to_string(),
+            line_col: None,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line_col {
+            Some((line, col)) => write!(f, "line {}, column {}", line + 1, col + 1),
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+impl LabBuilderError {
+    pub fn missing_field(path: &str) -> LabBuilderError {
+        LabBuilderError::MissingField {
+            location: Location::new(path),
+        }
+    }
+
+    pub fn wrong_type(path: &str, expected: &str) -> LabBuilderError {
+        LabBuilderError::WrongType {
+            location: Location::new(path),
+            expected: expected.to_string(),
+        }
+    }
+
+    pub fn invalid_value(path: &str, message: &str) -> LabBuilderError {
+        LabBuilderError::InvalidValue {
+            location: Location::new(path),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn location(&self) -> &Location {
+        match self {
+            LabBuilderError::Syntax { location, .. }
+            | LabBuilderError::MissingField { location }
+            | LabBuilderError::WrongType { location, .. }
+            | LabBuilderError::UnknownField { location, .. }
+            | LabBuilderError::InvalidValue { location, .. }
+            | LabBuilderError::DuplicateName { location, .. }
+            | LabBuilderError::UnknownNetworkReference { location, .. }
+            | LabBuilderError::SubnetExhausted { location, .. }
+            | LabBuilderError::HashedAddressTaken { location, .. }
+            | LabBuilderError::UnusedNetwork { location, .. }
+            | LabBuilderError::HostPortInUse { location, .. }
+            | LabBuilderError::NoRoute { location, .. }
+            | LabBuilderError::AmbiguousRoute { location, .. }
+            | LabBuilderError::MissingBridgeInterface { location, .. }
+            | LabBuilderError::DuplicateMacAddress { location, .. } => location,
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut Location {
+        match self {
+            LabBuilderError::Syntax { location, .. }
+            | LabBuilderError::MissingField { location }
+            | LabBuilderError::WrongType { location, .. }
+            | LabBuilderError::UnknownField { location, .. }
+            | LabBuilderError::InvalidValue { location, .. }
+            | LabBuilderError::DuplicateName { location, .. }
+            | LabBuilderError::UnknownNetworkReference { location, .. }
+            | LabBuilderError::SubnetExhausted { location, .. }
+            | LabBuilderError::HashedAddressTaken { location, .. }
+            | LabBuilderError::UnusedNetwork { location, .. }
+            | LabBuilderError::HostPortInUse { location, .. }
+            | LabBuilderError::NoRoute { location, .. }
+            | LabBuilderError::AmbiguousRoute { location, .. }
+            | LabBuilderError::MissingBridgeInterface { location, .. }
+            | LabBuilderError::DuplicateMacAddress { location, .. } => location,
+        }
+    }
+
+    /// Prefixes the path of this error with the path of the table it was found in, so errors
+    /// raised while parsing a single network or system point at their place in the scenario.
+    pub fn within(mut self, prefix: &str) -> LabBuilderError {
+        let location = self.location_mut();
+        location.path = match location.path.as_str() {
+            "" => prefix.to_string(),
+            path if path.starts_with('[') => format!("{}{}", prefix, path),
+            path => format!("{}.{}", prefix, path),
+        };
+
+        self
+    }
+
+    /// Fills in the line and column of this error by looking up its path in the TOML source it
+    /// was parsed from. Errors that already have a line and column are left untouched.
+    pub fn with_source(mut self, source: &str) -> LabBuilderError {
+        let location = self.location_mut();
+        if location.line_col.is_none() {
+            location.line_col = locate_path(source, &location.path);
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for LabBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LabBuilderError::Syntax { message, .. } => {
+                write!(f, "Could not parse scenario: {}", message)
+            }
+            LabBuilderError::MissingField { location } => {
+                write!(f, r#"Missing required field "{}""#, location.path)
+            }
+            LabBuilderError::WrongType { location, expected } => write!(
+                f,
+                r#"Field "{}" has the wrong type. Expected {}."#,
+                location.path, expected
+            ),
+            LabBuilderError::UnknownField {
+                location,
+                suggestion,
+            } => match suggestion {
+                Some(suggestion) => write!(
+                    f,
+                    r#"Unknown field "{}". Did you mean "{}"?"#,
+                    location.path, suggestion
+                ),
+                None => write!(f, r#"Unknown field "{}""#, location.path),
+            },
+            LabBuilderError::InvalidValue { location, message } => {
+                write!(f, r#"Invalid value for "{}": {}"#, location.path, message)
+            }
+            LabBuilderError::DuplicateName { kind, name, .. } => write!(
+                f,
+                r#"Multiple {}s parsed with name "{}". {} names must be unique."#,
+                kind,
+                name,
+                capitalise(kind)
+            ),
+            LabBuilderError::UnknownNetworkReference {
+                system, network, ..
+            } => write!(
+                f,
+                r#"System "{}" is configured to use network "{}" but no network with that name could be found"#,
+                system, network
+            ),
+            LabBuilderError::SubnetExhausted { network, .. } => write!(
+                f,
+                r#"Subnet for network "{}" does not have enough available addresses for all systems configured to use it."#,
+                network
+            ),
+            LabBuilderError::HashedAddressTaken {
+                network, address, ..
+            } => write!(
+                f,
+                r#"Address {} on network "{}", which this NIC hashes to, is taken, and so is every other address in the subnet."#,
+                address, network
+            ),
+            LabBuilderError::UnusedNetwork { network, .. } => {
+                write!(f, r#"Network "{}" is not used by any system."#, network)
+            }
+            LabBuilderError::HostPortInUse {
+                port,
+                protocol,
+                system,
+                ..
+            } => write!(
+                f,
+                r#"Host port {}/{} is already forwarded to system "{}"."#,
+                port, protocol, system
+            ),
+            LabBuilderError::NoRoute {
+                system, network, ..
+            } => write!(
+                f,
+                r#"System "{}" has no route to network "{}" through the scenario's routers."#,
+                system, network
+            ),
+            LabBuilderError::AmbiguousRoute {
+                system,
+                network,
+                routers,
+                ..
+            } => write!(
+                f,
+                r#"System "{}" can reach network "{}" equally well through routers "{}". Only one router may be used."#,
+                system,
+                network,
+                routers.join(r#"", ""#)
+            ),
+            LabBuilderError::MissingBridgeInterface { interfaces, .. } => {
+                match interfaces.as_slice() {
+                    [interface] => write!(
+                        f,
+                        r#"Interface "{}" does not exist on this host, so it can't be bridged to."#,
+                        interface
+                    ),
+                    _ => write!(
+                        f,
+                        r#"None of the interfaces "{}" exist on this host, so none can be bridged to."#,
+                        interfaces.join(r#"", ""#)
+                    ),
+                }
+            }
+            LabBuilderError::DuplicateMacAddress { mac, system, .. } => write!(
+                f,
+                r#"MAC address {} is already used by a NIC of system "{}"."#,
+                mac, system
+            ),
+        }?;
+
+        match self.location().line_col {
+            Some(_) => write!(f, " ({})", self.location()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for LabBuilderError {}
+
+impl de::Error for LabBuilderError {
+    fn custom<T: fmt::Display>(msg: T) -> LabBuilderError {
+        LabBuilderError::invalid_value("", &msg.to_string())
+    }
+
+    fn invalid_type(_unexpected: de::Unexpected, expected: &dyn de::Expected) -> LabBuilderError {
+        LabBuilderError::wrong_type("", &expected.to_string())
+    }
+
+    fn invalid_value(unexpected: de::Unexpected, expected: &dyn de::Expected) -> LabBuilderError {
+        LabBuilderError::invalid_value("", &format!("Expected {}, found {}.", expected, unexpected))
+    }
+
+    fn unknown_variant(variant: &str, expected: &'static [&'static str]) -> LabBuilderError {
+        LabBuilderError::invalid_value(
+            "",
+            &format!(
+                r#""{}" is not a valid option. Valid options are: {}"#,
+                variant,
+                expected.join(", ")
+            ),
+        )
+    }
+
+    fn unknown_field(field: &str, expected: &'static [&'static str]) -> LabBuilderError {
+        LabBuilderError::UnknownField {
+            location: Location::new(field),
+            suggestion: closest_match(field, expected).map(str::to_string),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> LabBuilderError {
+        LabBuilderError::missing_field(field)
+    }
+}
+
+impl From<toml::de::Error> for LabBuilderError {
+    fn from(error: toml::de::Error) -> LabBuilderError {
+        LabBuilderError::Syntax {
+            location: Location {
+                path: "".to_string(),
+                line_col: error.line_col(),
+            },
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Picks the candidate most similar to a misspelt name, if any is close enough to be a
+/// plausible typo.
+pub fn closest_match<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|&candidate| (candidate, strsim::levenshtein(name, candidate)))
+        .filter(|&(candidate, distance)| distance <= (candidate.len() / 3).max(2))
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(candidate, _)| candidate)
+}
+
+fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Finds the line and column a path such as `systems[1].base_box` refers to in TOML source.
+/// Fields that are missing resolve to the header of the table they should have been in.
+fn locate_path(source: &str, path: &str) -> Option<(usize, usize)> {
+    let mut segments = path.split('.');
+    let (table, index) = parse_segment(segments.next()?);
+
+    let header = match index {
+        Some(_) => format!("[[{}]]", table),
+        None => format!("[{}]", table),
+    };
+
+    let lines: Vec<&str> = source.lines().collect();
+    let (header_line, _) = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim() == header)
+        .nth(index.unwrap_or(0))?;
+
+    let header_col = lines[header_line].find('[')?;
+    let key = match segments.next() {
+        Some(segment) => parse_segment(segment).0,
+        None => return Some((header_line, header_col)),
+    };
+
+    let key_line = lines
+        .iter()
+        .enumerate()
+        .skip(header_line + 1)
+        .take_while(|(_, line)| !line.trim_start().starts_with('['))
+        .find(|(_, line)| {
+            let trimmed = line.trim_start();
+            trimmed.starts_with(key) && trimmed[key.len()..].trim_start().starts_with('=')
+        });
+
+    match key_line {
+        Some((line, text)) => Some((line, text.len() - text.trim_start().len())),
+        None => Some((header_line, header_col)),
+    }
+}
+
+fn parse_segment(segment: &str) -> (&str, Option<usize>) {
+    match segment.find('[') {
+        Some(open) => (
+            &segment[..open],
+            segment[open + 1..].trim_end_matches(']').parse().ok(),
+        ),
+        None => (segment, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"[scenario]
+name = "Test scenario"
+
+[[systems]]
+name = "Desktop"
+base_box = "Windows 10"
+
+[[systems]]
+name = "Server"
+  base_box = 42
+"#;
+
+    #[test]
+    fn within_should_prefix_error_path() {
+        let error = LabBuilderError::missing_field("name").within("networks[2]");
+
+        assert_eq!(error.location().path, "networks[2].name");
+    }
+
+    #[test]
+    fn within_should_join_array_indices_without_separator() {
+        let error = LabBuilderError::wrong_type("", "a string")
+            .within("[1]")
+            .within("networks");
+
+        assert_eq!(error.location().path, "networks[1]");
+    }
+
+    #[test]
+    fn closest_match_should_suggest_likely_typos_only() {
+        let candidates = ["name", "networks", "base_box"];
+
+        assert_eq!(closest_match("base_bx", &candidates), Some("base_box"));
+        assert_eq!(closest_match("netwroks", &candidates), Some("networks"));
+        assert_eq!(closest_match("memory", &candidates), None);
+    }
+
+    #[test]
+    fn with_source_should_locate_key_in_array_of_tables() {
+        let error =
+            LabBuilderError::wrong_type("systems[1].base_box", "a string").with_source(SOURCE);
+
+        assert_eq!(error.location().line_col, Some((9, 2)));
+        assert_eq!(
+            error.to_string(),
+            r#"Field "systems[1].base_box" has the wrong type. Expected a string. (line 10, column 3)"#
+        );
+    }
+
+    #[test]
+    fn with_source_should_locate_missing_field_at_table_header() {
+        let error = LabBuilderError::missing_field("systems[0].networks").with_source(SOURCE);
+
+        assert_eq!(error.location().line_col, Some((3, 0)));
+    }
+
+    #[test]
+    fn with_source_should_leave_unlocatable_paths_alone() {
+        let error = LabBuilderError::missing_field("networks").with_source(SOURCE);
+
+        assert_eq!(error.location().line_col, None);
+    }
+}
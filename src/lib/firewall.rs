@@ -0,0 +1,516 @@
+use crate::error::LabBuilderError;
+use crate::indentation_aware_string_builder::{IndentationAwareStringBuilder, IndentationType};
+use crate::resolved_scenario::{ResolvedScenario, ResolvedSystem};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// Filtering of the traffic routers forward between networks. Rules are checked in order and the
+/// first match decides what happens; traffic no rule matches gets the policy.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Firewall {
+    pub policy: FirewallAction,
+    pub tool: FirewallTool,
+    pub rules: Vec<FirewallRule>,
+}
+
+/// A rule matching forwarded traffic between a source and destination, each the name of a
+/// network or a system. A rule without a source or destination matches any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRule {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub protocol: FirewallProtocol,
+    pub port: Option<u16>,
+    pub action: FirewallAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "Any, Tcp, Udp or Icmp")]
+pub enum FirewallProtocol {
+    #[default]
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "Accept, Drop or Reject")]
+pub enum FirewallAction {
+    #[default]
+    Accept,
+    Drop,
+    Reject,
+}
+
+/// The tool routers are configured with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "Nftables or Iptables")]
+pub enum FirewallTool {
+    #[default]
+    Nftables,
+    Iptables,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a firewall rule table")]
+pub struct FirewallRuleDefinition {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub protocol: FirewallProtocol,
+    pub port: Option<u16>,
+    pub action: FirewallAction,
+}
+
+impl fmt::Display for FirewallProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirewallProtocol::Any => write!(f, "any"),
+            FirewallProtocol::Tcp => write!(f, "tcp"),
+            FirewallProtocol::Udp => write!(f, "udp"),
+            FirewallProtocol::Icmp => write!(f, "icmp"),
+        }
+    }
+}
+
+impl fmt::Display for FirewallAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirewallAction::Accept => write!(f, "accept"),
+            FirewallAction::Drop => write!(f, "drop"),
+            FirewallAction::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+impl fmt::Display for FirewallTool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirewallTool::Nftables => write!(f, "nftables"),
+            FirewallTool::Iptables => write!(f, "iptables"),
+        }
+    }
+}
+
+/// Shown as `DMZ -> LAN tcp/445 accept`, with `any` for a missing source or destination.
+impl fmt::Display for FirewallRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} {}",
+            self.source.as_deref().unwrap_or("any"),
+            self.destination.as_deref().unwrap_or("any"),
+            self.protocol
+        )?;
+        if let Some(port) = self.port {
+            write!(f, "/{}", port)?;
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+impl Firewall {
+    /// Whether the firewall changes anything, as routers forward everything when it doesn't.
+    pub fn is_active(&self) -> bool {
+        !self.rules.is_empty() || self.policy != FirewallAction::Accept
+    }
+}
+
+impl FirewallRule {
+    pub fn from_definition(
+        definition: FirewallRuleDefinition,
+    ) -> Result<FirewallRule, LabBuilderError> {
+        match (definition.protocol, definition.port) {
+            (FirewallProtocol::Any, Some(_)) | (FirewallProtocol::Icmp, Some(_)) => {
+                return Err(LabBuilderError::invalid_value(
+                    "port",
+                    "Only Tcp and Udp rules can match a port.",
+                ))
+            }
+            (_, Some(0)) => {
+                return Err(LabBuilderError::invalid_value(
+                    "port",
+                    "Port 0 can't be matched.",
+                ))
+            }
+            _ => (),
+        }
+
+        Ok(FirewallRule {
+            source: definition.source,
+            destination: definition.destination,
+            protocol: definition.protocol,
+            port: definition.port,
+            action: definition.action,
+        })
+    }
+
+    /// The names the rule refers to, along with the field naming them.
+    pub fn endpoints(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.source
+            .as_deref()
+            .map(|name| ("source", name))
+            .into_iter()
+            .chain(
+                self.destination
+                    .as_deref()
+                    .map(|name| ("destination", name)),
+            )
+    }
+}
+
+/// A rule narrowed to one pair of addresses. Rules between two named endpoints become one of
+/// these for every pair of addresses of the same family; `v6` is unset only when neither end is
+/// named, so the match applies to both families.
+struct CompiledRule<'a> {
+    rule: &'a FirewallRule,
+    v6: Option<bool>,
+    source: Option<IpNet>,
+    destination: Option<IpNet>,
+}
+
+/// A shell script run on every boot of a router, replacing its forwarding rules with the
+/// scenario's. Returns nothing for other systems, or when the firewall forwards everything.
+pub fn provisioning_script(scenario: &ResolvedScenario, system: &ResolvedSystem) -> Option<String> {
+    if !system.router || !scenario.firewall.is_active() {
+        return None;
+    }
+
+    match scenario.firewall.tool {
+        FirewallTool::Nftables => {
+            let mut builder = IndentationAwareStringBuilder::new();
+            builder.add("nft -f - <<'LABBUILDER'".to_string());
+            builder.add(nftables_ruleset(scenario));
+            builder.add("LABBUILDER".to_string());
+            Some(builder.build_string())
+        }
+        FirewallTool::Iptables => Some(iptables_script(scenario)),
+    }
+}
+
+/// An nftables ruleset filtering forwarded traffic in a table of its own, which is deleted and
+/// recreated whenever the ruleset is loaded.
+pub fn nftables_ruleset(scenario: &ResolvedScenario) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+    builder
+        .with_indentation_type(IndentationType::Spaces)
+        .with_tab_size(4);
+
+    builder.add("table inet labbuilder".to_string());
+    builder.add("delete table inet labbuilder".to_string());
+    builder.add("table inet labbuilder {".to_string());
+    builder.increase_indentation();
+    builder.add("chain forward {".to_string());
+    builder.increase_indentation();
+    builder.add(format!(
+        "type filter hook forward priority 0; policy {};",
+        match scenario.firewall.policy {
+            FirewallAction::Reject => FirewallAction::Drop,
+            policy => policy,
+        }
+    ));
+    builder.add("ct state established,related accept".to_string());
+
+    for compiled in compile(scenario) {
+        let mut matches = Vec::new();
+        let family = match compiled.v6 {
+            Some(true) => "ip6",
+            _ => "ip",
+        };
+        if let Some(source) = compiled.source {
+            matches.push(format!("{} saddr {}", family, source));
+        }
+        if let Some(destination) = compiled.destination {
+            matches.push(format!("{} daddr {}", family, destination));
+        }
+        match (compiled.rule.protocol, compiled.rule.port) {
+            (FirewallProtocol::Any, _) => (),
+            (FirewallProtocol::Icmp, _) => matches.push(
+                match compiled.v6 {
+                    Some(false) => "meta l4proto icmp",
+                    Some(true) => "meta l4proto ipv6-icmp",
+                    None => "meta l4proto { icmp, ipv6-icmp }",
+                }
+                .to_string(),
+            ),
+            (protocol, Some(port)) => matches.push(format!("{} dport {}", protocol, port)),
+            (protocol, None) => matches.push(format!("meta l4proto {}", protocol)),
+        }
+        matches.push(compiled.rule.action.to_string());
+        builder.add(matches.join(" "));
+    }
+
+    // The policy of a base chain can't reject, so rejecting is the chain's last rule.
+    if scenario.firewall.policy == FirewallAction::Reject {
+        builder.add("reject".to_string());
+    }
+
+    builder.decrease_indentation();
+    builder.add("}".to_string());
+    builder.decrease_indentation();
+    builder.add("}".to_string());
+
+    builder.build_string()
+}
+
+/// A shell script filtering forwarded traffic with iptables and ip6tables, through a chain of its
+/// own jumped to from `FORWARD`. The chain is emptied and refilled whenever the script runs.
+pub fn iptables_script(scenario: &ResolvedScenario) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+    let compiled = compile(scenario);
+
+    for (v6, command) in [(false, "iptables"), (true, "ip6tables")].iter() {
+        builder.add(format!(
+            "{0} -N LABBUILDER 2>/dev/null || {0} -F LABBUILDER",
+            command
+        ));
+        builder.add(format!(
+            "{0} -C FORWARD -j LABBUILDER 2>/dev/null || {0} -I FORWARD -j LABBUILDER",
+            command
+        ));
+        builder.add(format!(
+            "{} -A LABBUILDER -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT",
+            command
+        ));
+
+        for compiled in compiled
+            .iter()
+            .filter(|compiled| compiled.v6.is_none_or(|family| family == *v6))
+        {
+            let mut line = format!("{} -A LABBUILDER", command);
+            if let Some(source) = compiled.source {
+                line.push_str(&format!(" -s {}", source));
+            }
+            if let Some(destination) = compiled.destination {
+                line.push_str(&format!(" -d {}", destination));
+            }
+            match compiled.rule.protocol {
+                FirewallProtocol::Any => (),
+                FirewallProtocol::Icmp if *v6 => line.push_str(" -p ipv6-icmp"),
+                protocol => line.push_str(&format!(" -p {}", protocol)),
+            }
+            if let Some(port) = compiled.rule.port {
+                line.push_str(&format!(" --dport {}", port));
+            }
+            line.push_str(&format!(" -j {}", iptables_target(compiled.rule.action)));
+            builder.add(line);
+        }
+
+        builder.add(format!(
+            "{} -A LABBUILDER -j {}",
+            command,
+            iptables_target(scenario.firewall.policy)
+        ));
+    }
+
+    builder.build_string()
+}
+
+fn iptables_target(action: FirewallAction) -> &'static str {
+    match action {
+        FirewallAction::Accept => "ACCEPT",
+        FirewallAction::Drop => "DROP",
+        FirewallAction::Reject => "REJECT",
+    }
+}
+
+/// Narrows every rule to the addresses its endpoints stand for, in rule order. Rules naming an
+/// endpoint without addresses of a family aren't matched in that family at all.
+fn compile(scenario: &ResolvedScenario) -> Vec<CompiledRule<'_>> {
+    let mut compiled = Vec::new();
+
+    for rule in scenario.firewall.rules.iter() {
+        if rule.source.is_none() && rule.destination.is_none() {
+            compiled.push(CompiledRule {
+                rule,
+                v6: None,
+                source: None,
+                destination: None,
+            });
+            continue;
+        }
+
+        for v6 in [false, true].iter() {
+            let sources = endpoint_addresses(scenario, rule.source.as_deref(), *v6);
+            let destinations = endpoint_addresses(scenario, rule.destination.as_deref(), *v6);
+            for source in sources.iter() {
+                for destination in destinations.iter() {
+                    compiled.push(CompiledRule {
+                        rule,
+                        v6: Some(*v6),
+                        source: *source,
+                        destination: *destination,
+                    });
+                }
+            }
+        }
+    }
+
+    compiled
+}
+
+/// The addresses of one family an endpoint stands for: the subnet of a network, or the leased
+/// addresses of a system. A missing endpoint matches anything.
+fn endpoint_addresses(
+    scenario: &ResolvedScenario,
+    name: Option<&str>,
+    v6: bool,
+) -> Vec<Option<IpNet>> {
+    let name = match name {
+        Some(name) => name,
+        None => return vec![None],
+    };
+
+    if let Some(net) = scenario.networks.iter().find(|net| net.name == name) {
+        let subnet = match v6 {
+            false => net.subnet.map(|subnet| IpNet::V4(subnet.trunc())),
+            true => net.ipv6_subnet.map(|subnet| IpNet::V6(subnet.trunc())),
+        };
+        return subnet.into_iter().map(Some).collect();
+    }
+
+    scenario
+        .systems
+        .iter()
+        .filter(|system| system.name == name)
+        .flat_map(|system| system.nics.iter().filter_map(|nic| nic.lease))
+        .filter_map(|lease| match v6 {
+            false => lease.ipv4.map(IpAddr::V4),
+            true => lease.ipv6.map(IpAddr::V6),
+        })
+        .map(|addr| Some(IpNet::from(addr)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    fn firewalled_scenario(
+        tool: &str,
+    ) -> Result<ResolvedScenario, std::boxed::Box<dyn std::error::Error>> {
+        let input = format!(
+            r#"
+            [scenario]
+            name = "Test scenario"
+            firewall_tool = "{}"
+
+            [[systems]]
+            name = "Router"
+            networks = ["LAN", "DMZ"]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Web"
+            networks = ["DMZ"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "10.0.1.0/24"
+            ipv6_subnet = "fd00:1::/64"
+
+            [[networks]]
+            name = "DMZ"
+            type = "Internal"
+            subnet = "10.0.3.0/24"
+
+            [[firewall_rules]]
+            source = "Web"
+            destination = "LAN"
+            protocol = "Tcp"
+            port = 445
+            action = "Accept"
+
+            [[firewall_rules]]
+            source = "DMZ"
+            destination = "LAN"
+            action = "Drop"
+
+            [[firewall_rules]]
+            protocol = "Icmp"
+            action = "Accept"
+            "#,
+            tool
+        )
+        .parse::<Value>()?;
+
+        Ok(Scenario::from_toml(&input)?.resolve()?)
+    }
+
+    #[test]
+    fn nftables_ruleset_should_match_endpoint_addresses(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario = firewalled_scenario("Nftables")?;
+
+        let expected = "table inet labbuilder
+delete table inet labbuilder
+table inet labbuilder {
+    chain forward {
+        type filter hook forward priority 0; policy accept;
+        ct state established,related accept
+        ip saddr 10.0.3.2/32 ip daddr 10.0.1.0/24 tcp dport 445 accept
+        ip saddr 10.0.3.0/24 ip daddr 10.0.1.0/24 drop
+        meta l4proto { icmp, ipv6-icmp } accept
+    }
+}";
+
+        assert_eq!(nftables_ruleset(&scenario), expected);
+        assert!(provisioning_script(&scenario, &scenario.systems[0]).is_some());
+        assert_eq!(provisioning_script(&scenario, &scenario.systems[1]), None);
+        Ok(())
+    }
+
+    #[test]
+    fn iptables_script_should_fill_chains_for_both_families(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario = firewalled_scenario("Iptables")?;
+
+        let expected = "iptables -N LABBUILDER 2>/dev/null || iptables -F LABBUILDER
+iptables -C FORWARD -j LABBUILDER 2>/dev/null || iptables -I FORWARD -j LABBUILDER
+iptables -A LABBUILDER -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT
+iptables -A LABBUILDER -s 10.0.3.2/32 -d 10.0.1.0/24 -p tcp --dport 445 -j ACCEPT
+iptables -A LABBUILDER -s 10.0.3.0/24 -d 10.0.1.0/24 -j DROP
+iptables -A LABBUILDER -p icmp -j ACCEPT
+iptables -A LABBUILDER -j ACCEPT
+ip6tables -N LABBUILDER 2>/dev/null || ip6tables -F LABBUILDER
+ip6tables -C FORWARD -j LABBUILDER 2>/dev/null || ip6tables -I FORWARD -j LABBUILDER
+ip6tables -A LABBUILDER -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT
+ip6tables -A LABBUILDER -p ipv6-icmp -j ACCEPT
+ip6tables -A LABBUILDER -j ACCEPT";
+
+        assert_eq!(
+            provisioning_script(&scenario, &scenario.systems[0]),
+            Some(expected.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_rule_with_port_for_any_protocol_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            source = "DMZ"
+            port = 445
+            action = "Drop"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            FirewallRule::from_definition(crate::schema::from_value(&input)?).unwrap_err(),
+            LabBuilderError::invalid_value("port", "Only Tcp and Udp rules can match a port.")
+        );
+        Ok(())
+    }
+}
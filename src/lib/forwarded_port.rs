@@ -0,0 +1,133 @@
+use crate::error::LabBuilderError;
+
+use serde::Deserialize;
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// A port on the host forwarded to a port on a system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardedPort {
+    pub guest: u16,
+    pub host: u16,
+    pub protocol: Protocol,
+    /// The host address to listen on. Ports listen on every host address when unset.
+    pub host_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "Tcp or Udp")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a forwarded port table")]
+pub struct ForwardedPortDefinition {
+    pub guest: u16,
+    pub host: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub host_ip: Option<String>,
+}
+
+impl ForwardedPort {
+    pub fn from_definition(
+        definition: ForwardedPortDefinition,
+    ) -> Result<ForwardedPort, LabBuilderError> {
+        if definition.guest == 0 {
+            return Err(LabBuilderError::invalid_value(
+                "guest",
+                "Port 0 can't be forwarded.",
+            ));
+        }
+        if definition.host == 0 {
+            return Err(LabBuilderError::invalid_value(
+                "host",
+                "Port 0 can't be forwarded.",
+            ));
+        }
+
+        let host_ip = match definition.host_ip {
+            Some(host_ip) => Some(host_ip.parse::<IpAddr>().map_err(|_| {
+                LabBuilderError::invalid_value("host_ip", "Host IP is not a valid IP address.")
+            })?),
+            None => None,
+        };
+
+        Ok(ForwardedPort {
+            guest: definition.guest,
+            host: definition.host,
+            protocol: definition.protocol,
+            host_ip,
+        })
+    }
+
+    /// Whether both ports would need the same host port. A port listening on every host address
+    /// collides with any port using the same number and protocol. Listening on `0.0.0.0` or `::`
+    /// is listening on every host address.
+    pub fn collides_with(&self, other: &ForwardedPort) -> bool {
+        self.host == other.host
+            && self.protocol == other.protocol
+            && match (self.specific_host_ip(), other.specific_host_ip()) {
+                (Some(host_ip), Some(other_host_ip)) => host_ip == other_host_ip,
+                _ => true,
+            }
+    }
+
+    fn specific_host_ip(&self) -> Option<IpAddr> {
+        self.host_ip.filter(|host_ip| !host_ip.is_unspecified())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(host: u16, protocol: Protocol, host_ip: Option<&str>) -> ForwardedPort {
+        ForwardedPort {
+            guest: 80,
+            host,
+            protocol,
+            host_ip: host_ip.map(|host_ip| host_ip.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn forwarded_ports_should_collide_on_same_host_port_and_address() {
+        let any = port(8080, Protocol::Tcp, None);
+        let loopback = port(8080, Protocol::Tcp, Some("127.0.0.1"));
+
+        assert!(any.collides_with(&loopback));
+        assert!(loopback.collides_with(&loopback));
+        assert!(!loopback.collides_with(&port(8080, Protocol::Tcp, Some("127.0.0.2"))));
+        assert!(!any.collides_with(&port(8080, Protocol::Udp, None)));
+        assert!(!any.collides_with(&port(8081, Protocol::Tcp, None)));
+    }
+
+    #[test]
+    fn forwarded_ports_on_unspecified_address_should_collide_with_any_address() {
+        let loopback = port(8080, Protocol::Tcp, Some("127.0.0.1"));
+
+        assert!(port(8080, Protocol::Tcp, Some("0.0.0.0")).collides_with(&loopback));
+        assert!(loopback.collides_with(&port(8080, Protocol::Tcp, Some("::"))));
+        assert!(
+            !port(8080, Protocol::Tcp, Some("0.0.0.0")).collides_with(&port(
+                8080,
+                Protocol::Udp,
+                Some("::")
+            ))
+        );
+    }
+}
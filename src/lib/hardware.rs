@@ -0,0 +1,186 @@
+use crate::error::LabBuilderError;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The virtual hardware of a system. Anything left unset uses the base box's default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hardware {
+    pub cpus: Option<u32>,
+    pub memory: Option<Size>,
+    pub disks: Vec<Disk>,
+    pub gui: Option<bool>,
+}
+
+/// An extra disk attached to a system alongside the base box's own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disk {
+    pub size: Size,
+    pub bus: Option<DiskBus>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a disk table")]
+pub struct DiskDefinition {
+    pub size: Size,
+    pub bus: Option<DiskBus>,
+}
+
+/// The bus a disk is attached with. It's only used by libvirt, as VirtualBox attaches extra disks
+/// to the storage controller of the base box.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(expecting = "Virtio, Sata, Scsi or Ide")]
+pub enum DiskBus {
+    Virtio,
+    Sata,
+    Scsi,
+    Ide,
+}
+
+impl DiskBus {
+    pub fn libvirt_name(self) -> &'static str {
+        match self {
+            DiskBus::Virtio => "virtio",
+            DiskBus::Sata => "sata",
+            DiskBus::Scsi => "scsi",
+            DiskBus::Ide => "ide",
+        }
+    }
+}
+
+/// An amount of memory or storage in mebibytes. Scenarios give it as a number of mebibytes or as
+/// a string with a unit, such as "512MB" or "20GB". Units are binary, as in Vagrant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    pub mebibytes: u64,
+}
+
+impl Size {
+    pub fn parse(size: &str) -> Option<Size> {
+        let size = size.trim();
+        let split = size
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(size.len());
+        let (number, unit) = size.split_at(split);
+
+        let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "M" | "MB" | "MIB" => 1,
+            "G" | "GB" | "GIB" => 1024,
+            "T" | "TB" | "TIB" => 1024 * 1024,
+            _ => return None,
+        };
+
+        number
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(multiplier)
+            .map(|mebibytes| Size { mebibytes })
+    }
+
+    /// Formats the size with the largest whole unit, using the given suffixes for mebibytes and
+    /// gibibytes, as Vagrant and vagrant-libvirt spell them differently.
+    pub fn format_with(self, mebibyte_suffix: &str, gibibyte_suffix: &str) -> String {
+        match self.mebibytes % 1024 {
+            0 => format!("{}{}", self.mebibytes / 1024, gibibyte_suffix),
+            _ => format!("{}{}", self.mebibytes, mebibyte_suffix),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Size, D::Error> {
+        deserializer.deserialize_any(SizeVisitor)
+    }
+}
+
+struct SizeVisitor;
+
+impl<'de> Visitor<'de> for SizeVisitor {
+    type Value = Size;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, r#"a number of megabytes or a size such as "20GB""#)
+    }
+
+    fn visit_i64<E: de::Error>(self, mebibytes: i64) -> Result<Size, E> {
+        u64::try_from(mebibytes)
+            .map(|mebibytes| Size { mebibytes })
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(mebibytes), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, mebibytes: u64) -> Result<Size, E> {
+        Ok(Size { mebibytes })
+    }
+
+    fn visit_str<E: de::Error>(self, size: &str) -> Result<Size, E> {
+        Size::parse(size).ok_or_else(|| E::invalid_value(de::Unexpected::Str(size), &self))
+    }
+}
+
+impl Hardware {
+    /// Checks the amounts are usable, reporting errors against the fields of the system table.
+    pub fn from_definition(
+        cpus: Option<u32>,
+        memory: Option<Size>,
+        disks: Vec<DiskDefinition>,
+        gui: Option<bool>,
+    ) -> Result<Hardware, LabBuilderError> {
+        if cpus == Some(0) {
+            return Err(LabBuilderError::invalid_value(
+                "cpus",
+                "A system needs at least one CPU.",
+            ));
+        }
+        if memory.is_some_and(|memory| memory.mebibytes < 4) {
+            return Err(LabBuilderError::invalid_value(
+                "memory",
+                "A system needs at least 4MB of memory.",
+            ));
+        }
+
+        let mut checked_disks = Vec::new();
+        for (index, disk) in disks.into_iter().enumerate() {
+            if disk.size.mebibytes == 0 {
+                return Err(LabBuilderError::invalid_value(
+                    &format!("disks[{}].size", index),
+                    "Disks can't be empty.",
+                ));
+            }
+            checked_disks.push(Disk {
+                size: disk.size,
+                bus: disk.bus,
+            });
+        }
+
+        Ok(Hardware {
+            cpus,
+            memory,
+            disks: checked_disks,
+            gui,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_should_parse_numbers_with_binary_units() {
+        assert_eq!(Size::parse("512"), Some(Size { mebibytes: 512 }));
+        assert_eq!(Size::parse("512MB"), Some(Size { mebibytes: 512 }));
+        assert_eq!(Size::parse("20 GB"), Some(Size { mebibytes: 20480 }));
+        assert_eq!(Size::parse("1t"), Some(Size { mebibytes: 1048576 }));
+        assert_eq!(Size::parse("20 bananas"), None);
+        assert_eq!(Size::parse("GB"), None);
+    }
+
+    #[test]
+    fn size_should_format_with_largest_whole_unit() {
+        assert_eq!(Size { mebibytes: 2048 }.format_with("MB", "GB"), "2GB");
+        assert_eq!(Size { mebibytes: 1536 }.format_with("M", "G"), "1536M");
+    }
+}
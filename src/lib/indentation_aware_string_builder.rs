@@ -20,10 +20,10 @@ impl IndentationAwareStringBuilder {
         }
     }
 
-    pub fn with_indentation_type<'a>(
-        &'a mut self,
+    pub fn with_indentation_type(
+        &mut self,
         indentation_type: IndentationType,
-    ) -> &'a mut IndentationAwareStringBuilder {
+    ) -> &mut IndentationAwareStringBuilder {
         self.indentation_type = indentation_type;
 
         self.tab_size = match self.indentation_type {
@@ -34,10 +34,7 @@ impl IndentationAwareStringBuilder {
         self
     }
 
-    pub fn with_tab_size<'a>(
-        &'a mut self,
-        tab_size: usize,
-    ) -> &'a mut IndentationAwareStringBuilder {
+    pub fn with_tab_size(&mut self, tab_size: usize) -> &mut IndentationAwareStringBuilder {
         self.tab_size = Some(tab_size);
 
         self
@@ -49,7 +46,7 @@ impl IndentationAwareStringBuilder {
             IndentationType::Tabs => vec!["\t".to_string()],
         };
         let indentation = indent_string.iter().cloned().cycle();
-        let mut current_indentation = match self.indentation_type {
+        let current_indentation = match self.indentation_type {
             IndentationType::Spaces => indentation
                 .clone()
                 .take(self.current_indentation_level * self.tab_size.unwrap_or(4))
@@ -60,7 +57,7 @@ impl IndentationAwareStringBuilder {
                 .collect::<String>(),
         };
         self.buffer
-            .push(format!("{}{}", current_indentation, new_line).to_string());
+            .push(format!("{}{}", current_indentation, new_line));
     }
 
     pub fn increase_indentation(&mut self) {
@@ -75,3 +72,9 @@ impl IndentationAwareStringBuilder {
         self.buffer.join("\n")
     }
 }
+
+impl Default for IndentationAwareStringBuilder {
+    fn default() -> IndentationAwareStringBuilder {
+        IndentationAwareStringBuilder::new()
+    }
+}
@@ -0,0 +1,164 @@
+use crate::error::{LabBuilderError, Location};
+
+use toml::Value;
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl InputFormat {
+    pub fn from_name(name: &str) -> Option<InputFormat> {
+        match name {
+            "toml" => Some(InputFormat::Toml),
+            "yaml" | "yml" => Some(InputFormat::Yaml),
+            "json" => Some(InputFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<InputFormat> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| InputFormat::from_name(&extension.to_lowercase()))
+    }
+
+    /// Parses scenario source into the same value tree regardless of format, so every format
+    /// goes through the same schema and validation.
+    pub fn parse(self, source: &str) -> Result<Value, LabBuilderError> {
+        match self {
+            InputFormat::Toml => Ok(source.parse::<Value>()?),
+            InputFormat::Yaml => serde_yaml::from_str::<Value>(source).map_err(|e| {
+                syntax_error(
+                    e.to_string(),
+                    e.location().map(|l| (l.line() - 1, l.column() - 1)),
+                )
+            }),
+            InputFormat::Json => serde_json::from_str::<Value>(source).map_err(|e| {
+                let line_col = match e.line() {
+                    0 => None,
+                    line => Some((line - 1, e.column().saturating_sub(1))),
+                };
+                syntax_error(e.to_string(), line_col)
+            }),
+        }
+    }
+}
+
+fn syntax_error(message: String, line_col: Option<(usize, usize)>) -> LabBuilderError {
+    LabBuilderError::Syntax {
+        location: Location {
+            path: "".to_string(),
+            line_col,
+        },
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    const TOML_SCENARIO: &str = r#"
+        [scenario]
+        name = "Test scenario"
+        [[systems]]
+        name = "Test System"
+        networks = ["TestNet"]
+        base_box = "Debian"
+        [[networks]]
+        name = "TestNet"
+        type = "Internal"
+        subnet = "192.168.0.0/24"
+    "#;
+
+    const YAML_SCENARIO: &str = r#"
+scenario:
+  name: Test scenario
+systems:
+  - name: Test System
+    networks: [TestNet]
+    base_box: Debian
+networks:
+  - name: TestNet
+    type: Internal
+    subnet: 192.168.0.0/24
+"#;
+
+    const JSON_SCENARIO: &str = r#"{
+        "scenario": { "name": "Test scenario" },
+        "systems": [
+            { "name": "Test System", "networks": ["TestNet"], "base_box": "Debian" }
+        ],
+        "networks": [
+            { "name": "TestNet", "type": "Internal", "subnet": "192.168.0.0/24" }
+        ]
+    }"#;
+
+    #[test]
+    fn input_format_should_be_picked_from_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("lab.toml")),
+            Some(InputFormat::Toml)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("lab.YML")),
+            Some(InputFormat::Yaml)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("lab.json")),
+            Some(InputFormat::Json)
+        );
+        assert_eq!(InputFormat::from_path(Path::new("lab")), None);
+    }
+
+    #[test]
+    fn all_formats_should_produce_the_same_scenario(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let from_toml = Scenario::from_toml(&InputFormat::Toml.parse(TOML_SCENARIO)?)?;
+        let from_yaml = Scenario::from_toml(&InputFormat::Yaml.parse(YAML_SCENARIO)?)?;
+        let from_json = Scenario::from_toml(&InputFormat::Json.parse(JSON_SCENARIO)?)?;
+
+        assert_eq!(from_toml, from_yaml);
+        assert_eq!(from_toml, from_json);
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_scenario_should_produce_the_same_validation_errors(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let toml_input = TOML_SCENARIO.replace("base_box", "base_bx");
+        let yaml_input = YAML_SCENARIO.replace("base_box", "base_bx");
+
+        assert_eq!(
+            Scenario::validate(&InputFormat::Toml.parse(&toml_input)?).unwrap_err(),
+            Scenario::validate(&InputFormat::Yaml.parse(&yaml_input)?).unwrap_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_json_should_fail_with_location() {
+        let error = InputFormat::Json.parse("{\n  \"scenario\": }").unwrap_err();
+
+        assert_eq!(error.location().line_col, Some((1, 14)));
+    }
+
+    #[test]
+    fn yaml_null_values_should_be_rejected() {
+        let error = InputFormat::Yaml
+            .parse("scenario:\n  name: ~\n")
+            .unwrap_err();
+
+        match error {
+            LabBuilderError::Syntax { .. } => (),
+            _ => panic!("expected a syntax error, got {:?}", error),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::network;
+
+use std::fmt;
+
+/// The hardware address of a NIC, shown as lower case hex octets separated by colons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    /// A locally administered unicast address derived from `key`, so a NIC keeps the same
+    /// address every time the lab is built.
+    pub fn generate(key: &str) -> MacAddress {
+        // Keys for NICs of the same system only differ at the end, which FNV-1a barely spreads
+        // into the upper bits, so the hash is mixed again before its octets are used.
+        let mut hash = network::stable_hash(key);
+        hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        let hash = (hash ^ (hash >> 33)).to_be_bytes();
+        let mut octets = [0; 6];
+        octets.copy_from_slice(&hash[2..]);
+        octets[0] = (octets[0] & 0xfc) | 0x02;
+        MacAddress(octets)
+    }
+
+    /// Parses six pairs of hex digits separated by colons or dashes.
+    pub fn parse(mac: &str) -> Option<MacAddress> {
+        let separator = if mac.contains('-') { '-' } else { ':' };
+        let parts: Vec<&str> = mac.trim().split(separator).collect();
+        if parts.len() != 6 {
+            return None;
+        }
+
+        let mut octets = [0; 6];
+        for (octet, part) in octets.iter_mut().zip(parts) {
+            if part.len() != 2 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            *octet = u8::from_str_radix(part, 16).ok()?;
+        }
+        Some(MacAddress(octets))
+    }
+
+    /// Whether the address belongs to a single NIC, rather than a multicast group.
+    pub fn is_unicast(self) -> bool {
+        self.0[0] & 0x01 == 0
+    }
+
+    /// The address as twelve hex digits without separators, as VirtualBox expects.
+    pub fn to_hex(self) -> String {
+        self.0
+            .iter()
+            .map(|octet| format!("{:02X}", octet))
+            .collect()
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let octets: Vec<String> = self
+            .0
+            .iter()
+            .map(|octet| format!("{:02x}", octet))
+            .collect();
+        write!(f, "{}", octets.join(":"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_addresses_should_be_stable_locally_administered_unicast() {
+        let mac = MacAddress::generate("Test scenario/Desktop/0");
+
+        assert_eq!(mac, MacAddress::generate("Test scenario/Desktop/0"));
+        assert_ne!(mac, MacAddress::generate("Test scenario/Desktop/1"));
+        assert!(mac.is_unicast());
+        assert_eq!(mac.0[0] & 0x02, 0x02);
+    }
+
+    #[test]
+    fn parsing_addresses_should_accept_colons_or_dashes() {
+        let mac = MacAddress([0x08, 0x00, 0x27, 0xab, 0xcd, 0xef]);
+
+        assert_eq!(MacAddress::parse("08:00:27:ab:cd:ef"), Some(mac));
+        assert_eq!(MacAddress::parse("08-00-27-AB-CD-EF"), Some(mac));
+        assert_eq!(MacAddress::parse("08:00:27:ab:cd"), None);
+        assert_eq!(MacAddress::parse("08:00:27:ab:cd:eg"), None);
+        assert_eq!(mac.to_string(), "08:00:27:ab:cd:ef");
+        assert_eq!(mac.to_hex(), "080027ABCDEF");
+    }
+}
@@ -1,4 +1,23 @@
+pub mod backend;
+pub mod diagnostic;
+pub mod diagram;
+pub mod error;
+pub mod firewall;
+pub mod forwarded_port;
+pub mod hardware;
 pub mod indentation_aware_string_builder;
+pub mod input_format;
+pub mod mac_address;
+pub mod name_resolution;
 pub mod network;
+pub mod plan;
+pub mod provider;
+pub mod provisioner;
+pub mod resolved_scenario;
+pub mod routing;
+pub mod ruby;
 pub mod scenario;
+pub mod schema;
+pub mod synced_folder;
 pub mod system;
+pub mod unique_names;
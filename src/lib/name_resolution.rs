@@ -0,0 +1,362 @@
+use crate::error::LabBuilderError;
+use crate::indentation_aware_string_builder::IndentationAwareStringBuilder;
+use crate::resolved_scenario::ResolvedScenario;
+use crate::unique_names;
+
+use serde::Deserialize;
+use unicode_casefold::UnicodeCaseFold;
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// How systems with `lab_names` set are told the names of every other system, each time they
+/// boot.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(expecting = "Hosts or Dnsmasq")]
+pub enum NameResolution {
+    /// The hosts fragment is added to the system's `/etc/hosts`.
+    Hosts,
+    /// dnsmasq is installed and started on the system, with the dnsmasq config, and made the
+    /// system's nameserver.
+    Dnsmasq,
+}
+
+pub const DEFAULT_DOMAIN: &str = "lab";
+
+/// Lines between these markers are replaced whenever a system is provisioned again.
+const BEGIN_MARKER: &str = "# BEGIN LabBuilder";
+const END_MARKER: &str = "# END LabBuilder";
+
+/// Where the nameservers a system had before dnsmasq replaced them are kept, so dnsmasq can still
+/// forward names outside the lab domain to them.
+const UPSTREAM_RESOLV_CONF: &str = "/etc/resolv.dnsmasq.conf";
+
+/// The name of a system on one of its networks, formed from the system name, the network name
+/// and the lab domain, such as `desktop.lan.lab`.
+#[derive(Debug, PartialEq)]
+pub struct HostRecord {
+    pub name: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+/// Checks that a domain is made up of DNS labels: letters, digits and dashes, separated by dots.
+pub fn check_domain(domain: &str) -> Result<(), LabBuilderError> {
+    let valid = domain.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    match valid {
+        true => Ok(()),
+        false => Err(LabBuilderError::invalid_value(
+            "domain",
+            "Domain must be DNS labels of letters, digits and dashes separated by dots.",
+        )),
+    }
+}
+
+pub fn host_records(scenario: &ResolvedScenario) -> Vec<HostRecord> {
+    let system_labels = unique_labels(scenario.systems.iter().map(|s| s.name.as_str()));
+    let network_labels = unique_labels(scenario.networks.iter().map(|n| n.name.as_str()));
+    let domain = scenario.domain.to_ascii_lowercase();
+
+    let mut records = Vec::new();
+    for (system, system_label) in scenario.systems.iter().zip(system_labels.iter()) {
+        let mut named_networks = HashSet::new();
+        for nic in system.nics.iter() {
+            let lease = match nic.lease {
+                Some(lease) => lease,
+                None => continue,
+            };
+            let network_index = scenario
+                .networks
+                .iter()
+                .position(|net| net.name == nic.network.name)
+                .unwrap_or_default();
+
+            // A second NIC on the same network would need the same name, so only the first
+            // is named.
+            if !named_networks.insert(network_index) {
+                continue;
+            }
+
+            records.push(HostRecord {
+                name: format!(
+                    "{}.{}.{}",
+                    system_label, network_labels[network_index], domain
+                ),
+                ipv4: lease.ipv4,
+                ipv6: lease.ipv6,
+            });
+        }
+    }
+
+    records
+}
+
+/// A fragment to append to `/etc/hosts`, wrapped in markers so it can be replaced later.
+pub fn hosts_fragment(scenario: &ResolvedScenario) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+
+    builder.add(BEGIN_MARKER.to_string());
+    for record in host_records(scenario) {
+        if let Some(ipv4) = record.ipv4 {
+            builder.add(format!("{} {}", ipv4, record.name));
+        }
+        if let Some(ipv6) = record.ipv6 {
+            builder.add(format!("{} {}", ipv6, record.name));
+        }
+    }
+    builder.add(END_MARKER.to_string());
+
+    builder.build_string()
+}
+
+/// A dnsmasq config answering for every name in the lab domain, without forwarding queries for
+/// it upstream.
+pub fn dnsmasq_config(scenario: &ResolvedScenario) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+    let domain = scenario.domain.to_ascii_lowercase();
+
+    builder.add(format!("domain={}", domain));
+    builder.add(format!("local=/{}/", domain));
+    for record in host_records(scenario) {
+        let addresses: Vec<String> = record
+            .ipv4
+            .map(|addr| addr.to_string())
+            .into_iter()
+            .chain(record.ipv6.map(|addr| addr.to_string()))
+            .collect();
+        builder.add(format!(
+            "host-record={},{}",
+            record.name,
+            addresses.join(",")
+        ));
+    }
+
+    builder.build_string()
+}
+
+/// A shell script installing the chosen name resolution on a system. It can be run repeatedly,
+/// replacing what was installed before.
+pub fn provisioning_script(scenario: &ResolvedScenario, name_resolution: NameResolution) -> String {
+    let mut builder = IndentationAwareStringBuilder::new();
+
+    match name_resolution {
+        NameResolution::Hosts => {
+            builder.add(format!(
+                "sed -i '/^{}$/,/^{}$/d' /etc/hosts",
+                BEGIN_MARKER, END_MARKER
+            ));
+            builder.add("cat >> /etc/hosts <<'LABBUILDER'".to_string());
+            builder.add(hosts_fragment(scenario));
+            builder.add("LABBUILDER".to_string());
+        }
+        NameResolution::Dnsmasq => {
+            let domain = scenario.domain.to_ascii_lowercase();
+
+            builder.add("if command -v dnsmasq > /dev/null; then".to_string());
+            builder.increase_indentation();
+            builder.add(":".to_string());
+            builder.decrease_indentation();
+            for (package_manager, install) in &[
+                (
+                    "apt-get",
+                    "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y dnsmasq",
+                ),
+                ("dnf", "dnf install -y dnsmasq"),
+                ("yum", "yum install -y dnsmasq"),
+                ("apk", "apk add dnsmasq"),
+            ] {
+                builder.add(format!(
+                    "elif command -v {} > /dev/null; then",
+                    package_manager
+                ));
+                builder.increase_indentation();
+                builder.add(install.to_string());
+                builder.decrease_indentation();
+            }
+            builder.add("fi".to_string());
+            builder.add("mkdir -p /etc/dnsmasq.d".to_string());
+            builder.add(format!(
+                "cat > /etc/dnsmasq.d/{}.conf <<'LABBUILDER'",
+                domain
+            ));
+            builder.add(dnsmasq_config(scenario));
+            builder.add("LABBUILDER".to_string());
+
+            // dnsmasq only listens on loopback, so it doesn't clash with a stub resolver such as
+            // systemd-resolved, and forwards everything else to the nameservers the system had.
+            builder.add(format!(
+                "[ -e {0} ] || cp -L /etc/resolv.conf {0}",
+                UPSTREAM_RESOLV_CONF
+            ));
+            builder.add("cat > /etc/dnsmasq.d/labbuilder-resolver.conf <<'LABBUILDER'".to_string());
+            builder.add("listen-address=127.0.0.1".to_string());
+            builder.add("bind-interfaces".to_string());
+            builder.add(format!("resolv-file={}", UPSTREAM_RESOLV_CONF));
+            builder.add("LABBUILDER".to_string());
+
+            builder.add("if command -v systemctl > /dev/null; then".to_string());
+            builder.increase_indentation();
+            builder.add("systemctl enable dnsmasq && systemctl restart dnsmasq".to_string());
+            builder.decrease_indentation();
+            builder.add("else".to_string());
+            builder.increase_indentation();
+            builder.add("rc-update add dnsmasq && rc-service dnsmasq restart".to_string());
+            builder.decrease_indentation();
+            builder.add("fi".to_string());
+
+            builder.add("rm -f /etc/resolv.conf".to_string());
+            builder.add(format!(
+                "printf 'nameserver 127.0.0.1\\nsearch {}\\n' > /etc/resolv.conf",
+                domain
+            ));
+        }
+    }
+
+    builder.build_string()
+}
+
+/// Turns names into unique DNS labels of lower case letters, digits and dashes. Names that
+/// sanitise to the same label are told apart by a numeric suffix.
+fn unique_labels<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Vec<String> {
+    unique_names::unique_names(names, label, "-", &[])
+}
+
+fn label(name: &str) -> String {
+    let folded: String = name
+        .case_fold()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' => c,
+            _ => '-',
+        })
+        .collect();
+    let mut label = folded.trim_matches('-').to_string();
+    if label.is_empty() {
+        label.push('x');
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    fn named_scenario() -> Result<ResolvedScenario, std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            domain = "Example.Lab"
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN", "WAN"]
+            base_box = "Windows 10"
+
+            [[systems]]
+            name = "File Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/64"
+
+            [[networks]]
+            name = "WAN"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        Ok(Scenario::from_toml(&input)?.resolve()?)
+    }
+
+    #[test]
+    fn hosts_fragment_should_name_every_leased_address(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let expected = "# BEGIN LabBuilder
+192.168.0.1 desktop.lan.example.lab
+fd00::1 desktop.lan.example.lab
+192.168.0.2 file-server.lan.example.lab
+fd00::2 file-server.lan.example.lab
+# END LabBuilder";
+
+        assert_eq!(hosts_fragment(&named_scenario()?), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn dnsmasq_config_should_answer_for_lab_domain(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let expected = "domain=example.lab
+local=/example.lab/
+host-record=desktop.lan.example.lab,192.168.0.1,fd00::1
+host-record=file-server.lan.example.lab,192.168.0.2,fd00::2";
+
+        assert_eq!(dnsmasq_config(&named_scenario()?), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn dnsmasq_provisioning_script_should_install_dnsmasq_and_use_it_as_nameserver(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let expected = r#"if command -v dnsmasq > /dev/null; then
+    :
+elif command -v apt-get > /dev/null; then
+    apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y dnsmasq
+elif command -v dnf > /dev/null; then
+    dnf install -y dnsmasq
+elif command -v yum > /dev/null; then
+    yum install -y dnsmasq
+elif command -v apk > /dev/null; then
+    apk add dnsmasq
+fi
+mkdir -p /etc/dnsmasq.d
+cat > /etc/dnsmasq.d/example.lab.conf <<'LABBUILDER'
+domain=example.lab
+local=/example.lab/
+host-record=desktop.lan.example.lab,192.168.0.1,fd00::1
+host-record=file-server.lan.example.lab,192.168.0.2,fd00::2
+LABBUILDER
+[ -e /etc/resolv.dnsmasq.conf ] || cp -L /etc/resolv.conf /etc/resolv.dnsmasq.conf
+cat > /etc/dnsmasq.d/labbuilder-resolver.conf <<'LABBUILDER'
+listen-address=127.0.0.1
+bind-interfaces
+resolv-file=/etc/resolv.dnsmasq.conf
+LABBUILDER
+if command -v systemctl > /dev/null; then
+    systemctl enable dnsmasq && systemctl restart dnsmasq
+else
+    rc-update add dnsmasq && rc-service dnsmasq restart
+fi
+rm -f /etc/resolv.conf
+printf 'nameserver 127.0.0.1\nsearch example.lab\n' > /etc/resolv.conf"#;
+
+        assert_eq!(
+            provisioning_script(&named_scenario()?, NameResolution::Dnsmasq),
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_domain_should_reject_invalid_labels() {
+        assert_eq!(check_domain("lab"), Ok(()));
+        assert_eq!(check_domain("example.lab"), Ok(()));
+        assert!(check_domain("").is_err());
+        assert!(check_domain("example..lab").is_err());
+        assert!(check_domain("-lab").is_err());
+        assert!(check_domain("my lab").is_err());
+    }
+}
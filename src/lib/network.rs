@@ -1,7 +1,13 @@
-use ipnet::{Ipv4AddrRange, Ipv4Net};
+use crate::error::{LabBuilderError, Location};
+use crate::schema;
+
+use ipnet::{Ipv4AddrRange, Ipv4Net, Ipv6AddrRange, Ipv6Net};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Deserialize;
 use std::cell::RefCell;
 use std::collections::hash_set::HashSet;
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::rc::Rc;
 use toml::Value;
 
@@ -10,111 +16,246 @@ pub struct Network {
     pub name: String,
     pub network_type: NetworkType,
     pub subnet: Option<Ipv4Net>,
+    pub ipv6_subnet: Option<Ipv6Net>,
+    pub gateway: Option<Ipv4Addr>,
+    /// The address of the hypervisor host on a host-only network.
+    pub host_ip: Option<Ipv4Addr>,
+    pub reserved: Vec<Ipv4Range>,
+    pub dhcp_range: Option<Ipv4Range>,
+    /// Host interfaces a public network may be bridged to, in order of preference. Vagrant asks
+    /// which one to use when none are given.
+    pub bridge: Vec<String>,
     available_hosts: Option<Ipv4AddrRange>,
     allocated_hosts: Option<RefCell<HashSet<Ipv4Addr>>>,
+    available_ipv6_hosts: Option<Ipv6AddrRange>,
+    allocated_ipv6_hosts: Option<RefCell<HashSet<Ipv6Addr>>>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(expecting = "the name of a network type")]
 pub enum NetworkType {
+    /// Bridged onto the host's network, which hands out addresses itself.
     Public,
+    /// Only reachable by the systems attached to it.
     Internal,
+    /// Also reachable from the hypervisor host, which takes an address from the subnet.
+    HostOnly,
+    /// Given outbound access to the internet through the provider's NAT, whose gateway takes an
+    /// address from the subnet.
+    Nat,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a network table")]
+pub struct NetworkDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub network_type: NetworkType,
+    pub subnet: Option<String>,
+    pub ipv6_subnet: Option<String>,
+    pub gateway: Option<String>,
+    pub host_ip: Option<String>,
+    pub reserved: Option<Vec<String>>,
+    pub dhcp_range: Option<String>,
+    pub bridge: Option<Bridge>,
+}
+
+/// The interfaces to bridge to, given as a single name or a list of candidates.
+#[derive(Debug, PartialEq)]
+pub struct Bridge(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for Bridge {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bridge, D::Error> {
+        deserializer.deserialize_any(BridgeVisitor)
+    }
+}
+
+struct BridgeVisitor;
+
+impl<'de> Visitor<'de> for BridgeVisitor {
+    type Value = Bridge;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an interface name or a list of interface names")
+    }
+
+    fn visit_str<E: de::Error>(self, interface: &str) -> Result<Bridge, E> {
+        Ok(Bridge(vec![interface.to_string()]))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bridge, A::Error> {
+        let mut interfaces = Vec::new();
+        while let Some(interface) = seq.next_element::<String>()? {
+            interfaces.push(interface);
+        }
+        Ok(Bridge(interfaces))
+    }
+}
+
+/// An inclusive range of IPv4 addresses the allocator must not lease from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv4Range {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+impl Ipv4Range {
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+impl fmt::Display for Ipv4Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+
+/// How dynamic addresses are picked. `Sequential` hands out the lowest free address, so leases
+/// depend on the order systems are configured in. `Hashed` starts from an address derived from
+/// the system and NIC, so existing systems keep their addresses as the scenario grows. NICs
+/// hashing to a taken address probe forward to the next free one, in system name order, so the
+/// result doesn't depend on the order of the scenario file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "the name of an allocation mode")]
+pub enum AllocationMode {
+    #[default]
+    Sequential,
+    Hashed,
+}
+
+/// The addresses leased to a single NIC. Dual-stack networks lease one address of each family.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Lease {
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
 }
 
 impl Network {
-    pub fn from_toml(
-        network_toml: &Value,
-    ) -> Result<Rc<Network>, std::boxed::Box<std::error::Error>> {
-        let network_name = network_toml
-            .get("name")
-            .ok_or("Could not read network name")?
-            .as_str()
-            .ok_or("Could not read network name as a string")?
-            .into();
-
-        let network_type_unparsed = network_toml
-            .get("type")
-            .ok_or(format!(
-                "Could not read network type for network: {}",
-                network_name
-            ))?
-            .as_str()
-            .ok_or(format!(
-                "Could not read network type as a string for network: {}",
-                network_name
-            ))?
-            .into();
-
-        let network_type = match network_type_unparsed {
-            "Public" => Ok(NetworkType::Public),
-            "Internal" => Ok(NetworkType::Internal),
-            _ => Err(format!(
-                "Could not parse network type as a valid type for network: {}. Valid types are: Public, Internal",
-                network_name
-            )),
-        }?;
+    pub fn from_toml(network_toml: &Value) -> Result<Rc<Network>, LabBuilderError> {
+        Network::from_definition(schema::from_value(network_toml)?)
+    }
 
+    pub fn from_definition(definition: NetworkDefinition) -> Result<Rc<Network>, LabBuilderError> {
         let mut subnet: Option<Ipv4Net> = None;
-        let mut available_hosts: Option<Ipv4AddrRange> = None;
-        let mut allocated_hosts: Option<RefCell<HashSet<Ipv4Addr>>> = None;
-
-        if network_type == NetworkType::Internal {
-            subnet = Some(
-                network_toml
-                    .get("subnet")
-                    .ok_or(format!(
-                        "Could not read subnet for network: {}",
-                        network_name
-                    ))?
-                    .as_str()
-                    .ok_or(format!(
-                        "Could not read subnet as string for network: {}",
-                        network_name
-                    ))?
-                    .parse()
-                    .map_err(|_| {
-                        format!(
-                            "Could not parse subnet as a valid CIDR range for network: {}",
-                            network_name
-                        )
-                    })
-                    .and_then(|subnet: Ipv4Net| {
-                        return match subnet.prefix_len() {
-                            0...30 => Ok(subnet),
-                            _ => Err(format!(r#"Network "{}" configured with a subnet smaller than /30. Networks smaller than /30 can't have multiple hosts."#, network_name))
-                        }
-                    })
-                    .and_then(|subnet: Ipv4Net| {
-                        let private_nets = vec![
-                            "10.0.0.0/8".parse::<Ipv4Net>().unwrap(),
-                            "172.16.0.0/12".parse::<Ipv4Net>().unwrap(),
-                            "192.168.0.0/16".parse::<Ipv4Net>().unwrap(),
-                        ];
-
-                        let privacy_result = private_nets.iter()
-                            .any(|&priv_net| priv_net.contains(&subnet));
-
-                        return match privacy_result {
-                            true => Ok(subnet),
-                            false => Err(format!(r#"Subnet configured for network "{}" is not RFC 1918 compliant. Subnets must be in valid allocation for private networks."#, network_name))
-                        };
-                    })?
-            );
-
-            available_hosts = Some(subnet.unwrap().hosts());
-            allocated_hosts = Some(RefCell::new(HashSet::new()));
-        } else {
-            match network_toml.get("subnet") {
-                None => Ok(()),
-                Some(_) => Err(format!(r#"Network "{}" is configured as a Public network and has a subnet configured. Public networks can't have configured subnets."#, network_name)),
-            }?
+        let mut ipv6_subnet: Option<Ipv6Net> = None;
+        let mut gateway: Option<Ipv4Addr> = None;
+        let mut host_ip: Option<Ipv4Addr> = None;
+        let mut reserved: Vec<Ipv4Range> = Vec::new();
+        let mut dhcp_range: Option<Ipv4Range> = None;
+
+        if definition.host_ip.is_some() && definition.network_type != NetworkType::HostOnly {
+            return Err(LabBuilderError::invalid_value(
+                "host_ip",
+                "Only HostOnly networks have a host IP.",
+            ));
         }
 
+        if definition.network_type != NetworkType::Public {
+            if definition.bridge.is_some() {
+                return Err(LabBuilderError::invalid_value(
+                    "bridge",
+                    "Only Public networks are bridged.",
+                ));
+            }
+
+            // The host or the NAT gateway takes an address from the IPv4 subnet, so those
+            // networks can't be IPv6 only.
+            let needs_ipv4 = definition.network_type != NetworkType::Internal;
+            if definition.subnet.is_none() && (needs_ipv4 || definition.ipv6_subnet.is_none()) {
+                return Err(LabBuilderError::missing_field("subnet"));
+            }
+
+            subnet = definition.subnet.map(parse_subnet).transpose()?;
+            ipv6_subnet = definition.ipv6_subnet.map(parse_ipv6_subnet).transpose()?;
+
+            if let Some(gateway_definition) = definition.gateway {
+                gateway = Some(parse_address_in_subnet(
+                    subnet,
+                    &gateway_definition,
+                    "gateway",
+                    "Gateway must be a single address.",
+                )?);
+            }
+
+            match definition.network_type {
+                NetworkType::HostOnly => {
+                    host_ip = match definition.host_ip {
+                        Some(host_ip) => Some(parse_address_in_subnet(
+                            subnet,
+                            &host_ip,
+                            "host_ip",
+                            "Host IP must be a single address.",
+                        )?),
+                        None => subnet.and_then(|subnet| subnet.hosts().next()),
+                    };
+                }
+                NetworkType::Nat => {
+                    gateway = gateway.or_else(|| subnet.and_then(|subnet| subnet.hosts().next()));
+                }
+                NetworkType::Internal | NetworkType::Public => (),
+            }
+
+            for (index, range) in definition.reserved.unwrap_or_default().iter().enumerate() {
+                reserved.push(parse_range_in_subnet(
+                    subnet,
+                    range,
+                    &format!("reserved[{}]", index),
+                )?);
+            }
+
+            dhcp_range = definition
+                .dhcp_range
+                .map(|range| parse_range_in_subnet(subnet, &range, "dhcp_range"))
+                .transpose()?;
+        } else if definition.gateway.is_some()
+            || definition.reserved.is_some()
+            || definition.dhcp_range.is_some()
+        {
+            return Err(LabBuilderError::invalid_value(
+                "type",
+                "Public networks can't have configured gateways, reserved addresses or DHCP ranges.",
+            ));
+        } else if definition.subnet.is_some() {
+            return Err(LabBuilderError::invalid_value(
+                "subnet",
+                "Public networks can't have configured subnets.",
+            ));
+        } else if definition.ipv6_subnet.is_some() {
+            return Err(LabBuilderError::invalid_value(
+                "ipv6_subnet",
+                "Public networks can't have configured subnets.",
+            ));
+        }
+
+        let bridge = match definition.bridge {
+            Some(Bridge(interfaces)) if interfaces.is_empty() => {
+                return Err(LabBuilderError::invalid_value(
+                    "bridge",
+                    "At least one interface must be given to bridge to.",
+                ))
+            }
+            Some(Bridge(interfaces)) => interfaces,
+            None => Vec::new(),
+        };
+
         Ok(Rc::new(Network {
-            name: network_name,
-            network_type: network_type,
-            subnet: subnet,
-            available_hosts: available_hosts,
-            allocated_hosts: allocated_hosts,
+            name: definition.name,
+            network_type: definition.network_type,
+            subnet,
+            ipv6_subnet,
+            gateway,
+            host_ip,
+            reserved,
+            dhcp_range,
+            bridge,
+            available_hosts: subnet.map(|subnet| subnet.hosts()),
+            allocated_hosts: subnet.map(|_| RefCell::new(HashSet::new())),
+            available_ipv6_hosts: ipv6_subnet.map(|subnet| subnet.hosts()),
+            allocated_ipv6_hosts: ipv6_subnet.map(|_| RefCell::new(HashSet::new())),
         }))
     }
 
@@ -122,18 +263,452 @@ impl Network {
         if let Some(allocated_hosts) = &self.allocated_hosts {
             let leased_addr = self
                 .available_hosts?
-                .skip_while(|addr| allocated_hosts.borrow().contains(addr))
-                .next();
-            return match leased_addr {
-                Some(addr) => {
-                    allocated_hosts.borrow_mut().insert(addr);
-                    return Some(addr);
+                .find(|addr| !self.is_excluded(*addr) && !allocated_hosts.borrow().contains(addr));
+            if let Some(addr) = leased_addr {
+                allocated_hosts.borrow_mut().insert(addr);
+            }
+            return leased_addr;
+        }
+        None
+    }
+
+    /// Leases the next free IPv6 address. The all-zeros address of the prefix is the
+    /// subnet-router anycast address, so it is never handed out.
+    pub fn get_ipv6_address_lease(&self) -> Option<Ipv6Addr> {
+        if let Some(allocated_hosts) = &self.allocated_ipv6_hosts {
+            let subnet_router = self.ipv6_subnet?.network();
+            let leased_addr = self
+                .available_ipv6_hosts?
+                .find(|addr| *addr != subnet_router && !allocated_hosts.borrow().contains(addr));
+            if let Some(addr) = leased_addr {
+                allocated_hosts.borrow_mut().insert(addr);
+            }
+            return leased_addr;
+        }
+        None
+    }
+
+    /// Leases a free address starting from a position derived from `key`, probing forward
+    /// through the subnet past addresses that are excluded or already taken. Fails only when
+    /// every address is.
+    pub fn get_hashed_address_lease(&self, key: &str) -> Result<Ipv4Addr, LabBuilderError> {
+        let (subnet, allocated_hosts) = match (self.subnet, &self.allocated_hosts) {
+            (Some(subnet), Some(allocated_hosts)) => (subnet, allocated_hosts),
+            _ => return Err(self.exhausted()),
+        };
+
+        let first_host = u32::from(subnet.network()) + 1;
+        let host_count = u32::from(subnet.broadcast()) - first_host;
+        let start = (stable_hash(key) % u64::from(host_count)) as u32;
+
+        let addr = (0..host_count)
+            .map(|offset| Ipv4Addr::from(first_host + (start + offset) % host_count))
+            .find(|addr| !self.is_excluded(*addr) && !allocated_hosts.borrow().contains(addr))
+            .ok_or_else(|| self.hashed_address_taken(&Ipv4Addr::from(first_host + start)))?;
+        allocated_hosts.borrow_mut().insert(addr);
+        Ok(addr)
+    }
+
+    /// The IPv6 counterpart of `get_hashed_address_lease`. The subnet-router anycast address is
+    /// never handed out.
+    pub fn get_hashed_ipv6_address_lease(&self, key: &str) -> Result<Ipv6Addr, LabBuilderError> {
+        let (subnet, allocated_hosts) = match (self.ipv6_subnet, &self.allocated_ipv6_hosts) {
+            (Some(subnet), Some(allocated_hosts)) => (subnet, allocated_hosts),
+            _ => return Err(self.exhausted()),
+        };
+
+        let first_host = u128::from(subnet.network()) + 1;
+        let host_count = u128::from(subnet.broadcast()) - first_host + 1;
+        let start = u128::from(stable_hash(key)) % host_count;
+
+        let addr = (0..host_count)
+            .map(|offset| Ipv6Addr::from(first_host + (start + offset) % host_count))
+            .find(|addr| !allocated_hosts.borrow().contains(addr))
+            .ok_or_else(|| self.hashed_address_taken(&Ipv6Addr::from(first_host + start)))?;
+        allocated_hosts.borrow_mut().insert(addr);
+        Ok(addr)
+    }
+
+    fn exhausted(&self) -> LabBuilderError {
+        LabBuilderError::SubnetExhausted {
+            location: Location::new(""),
+            network: self.name.to_string(),
+        }
+    }
+
+    fn hashed_address_taken(&self, address: &dyn fmt::Display) -> LabBuilderError {
+        LabBuilderError::HashedAddressTaken {
+            location: Location::new(""),
+            network: self.name.to_string(),
+            address: address.to_string(),
+        }
+    }
+
+    /// Claims a statically reserved IPv4 address so it is never handed out as a dynamic lease.
+    pub fn reserve_address(&self, addr: Ipv4Addr) -> Result<(), LabBuilderError> {
+        let (subnet, allocated_hosts) = match (self.subnet, &self.allocated_hosts) {
+            (Some(subnet), Some(allocated_hosts)) => (subnet, allocated_hosts),
+            _ => {
+                return Err(LabBuilderError::invalid_value(
+                    "ip",
+                    &format!(
+                        r#"Network "{}" has no IPv4 subnet to reserve addresses in."#,
+                        self.name
+                    ),
+                ))
+            }
+        };
+
+        if !subnet.contains(&addr) || addr == subnet.network() || addr == subnet.broadcast() {
+            return Err(LabBuilderError::invalid_value(
+                "ip",
+                &format!(
+                    "Address {} is not a host address in subnet {}.",
+                    addr,
+                    subnet.trunc()
+                ),
+            ));
+        }
+
+        if self.is_excluded(addr) {
+            return Err(LabBuilderError::invalid_value(
+                "ip",
+                &format!(
+                    r#"Address {} is excluded from allocation on network "{}"."#,
+                    addr, self.name
+                ),
+            ));
+        }
+
+        if !allocated_hosts.borrow_mut().insert(addr) {
+            return Err(LabBuilderError::invalid_value(
+                "ip",
+                &format!(
+                    r#"Address {} is already reserved on network "{}"."#,
+                    addr, self.name
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Claims a statically reserved IPv6 address so it is never handed out as a dynamic lease.
+    pub fn reserve_ipv6_address(&self, addr: Ipv6Addr) -> Result<(), LabBuilderError> {
+        let (subnet, allocated_hosts) = match (self.ipv6_subnet, &self.allocated_ipv6_hosts) {
+            (Some(subnet), Some(allocated_hosts)) => (subnet, allocated_hosts),
+            _ => {
+                return Err(LabBuilderError::invalid_value(
+                    "ipv6",
+                    &format!(
+                        r#"Network "{}" has no IPv6 subnet to reserve addresses in."#,
+                        self.name
+                    ),
+                ))
+            }
+        };
+
+        if !subnet.contains(&addr) || addr == subnet.network() {
+            return Err(LabBuilderError::invalid_value(
+                "ipv6",
+                &format!(
+                    "Address {} is not a host address in subnet {}.",
+                    addr,
+                    subnet.trunc()
+                ),
+            ));
+        }
+
+        if !allocated_hosts.borrow_mut().insert(addr) {
+            return Err(LabBuilderError::invalid_value(
+                "ipv6",
+                &format!(
+                    r#"Address {} is already reserved on network "{}"."#,
+                    addr, self.name
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Leases an address from every address family configured on this network, using the
+    /// addresses of `reservation` where set. Reserved addresses must already have been claimed
+    /// with `reserve_address` or `reserve_ipv6_address`. In `Hashed` mode, `key` identifies the
+    /// NIC the lease is for. Fails if the network has no subnets or any of them is exhausted.
+    pub fn get_lease(
+        &self,
+        reservation: &Lease,
+        mode: AllocationMode,
+        key: &str,
+    ) -> Result<Lease, LabBuilderError> {
+        if self.subnet.is_none() && self.ipv6_subnet.is_none() {
+            return Err(self.exhausted());
+        }
+
+        let ipv4 = match (self.subnet, reservation.ipv4) {
+            (Some(_), Some(reserved)) => Some(reserved),
+            (Some(_), None) => Some(match mode {
+                AllocationMode::Sequential => {
+                    self.get_address_lease().ok_or_else(|| self.exhausted())?
                 }
-                None => None,
+                AllocationMode::Hashed => self.get_hashed_address_lease(key)?,
+            }),
+            (None, _) => None,
+        };
+        let ipv6 = match (self.ipv6_subnet, reservation.ipv6) {
+            (Some(_), Some(reserved)) => Some(reserved),
+            (Some(_), None) => Some(match mode {
+                AllocationMode::Sequential => self
+                    .get_ipv6_address_lease()
+                    .ok_or_else(|| self.exhausted())?,
+                AllocationMode::Hashed => self.get_hashed_ipv6_address_lease(key)?,
+            }),
+            (None, _) => None,
+        };
+
+        Ok(Lease { ipv4, ipv6 })
+    }
+
+    /// Whether an address is the gateway or host IP or falls in a reserved or DHCP range, and
+    /// so must never be leased to a system.
+    pub fn is_excluded(&self, addr: Ipv4Addr) -> bool {
+        self.gateway == Some(addr)
+            || self.host_ip == Some(addr)
+            || self.reserved.iter().any(|range| range.contains(addr))
+            || self.dhcp_range.is_some_and(|range| range.contains(addr))
+    }
+
+    /// Counts the host addresses of the subnet that are excluded from allocation, counting
+    /// addresses covered by more than one exclusion once.
+    fn excluded_host_count(&self) -> u64 {
+        let subnet = match self.subnet {
+            Some(subnet) => subnet,
+            None => return 0,
+        };
+        let first_host = u32::from(subnet.network()) + 1;
+        let last_host = u32::from(subnet.broadcast()) - 1;
+
+        let mut ranges: Vec<(u32, u32)> = self
+            .gateway
+            .into_iter()
+            .chain(self.host_ip)
+            .map(|addr| Ipv4Range {
+                start: addr,
+                end: addr,
+            })
+            .chain(self.reserved.iter().cloned())
+            .chain(self.dhcp_range)
+            .map(|range| {
+                (
+                    u32::from(range.start).max(first_host),
+                    u32::from(range.end).min(last_host),
+                )
+            })
+            .filter(|(start, end)| start <= end)
+            .collect();
+        ranges.sort_unstable();
+
+        let mut excluded = 0;
+        let mut covered_until: Option<u32> = None;
+        for (start, end) in ranges {
+            let start = match covered_until {
+                Some(covered) if covered >= end => continue,
+                Some(covered) if covered >= start => covered + 1,
+                _ => start,
             };
+            excluded += u64::from(end - start) + 1;
+            covered_until = Some(end);
         }
-        None
+
+        excluded
     }
+
+    pub fn allocated_host_count(&self) -> usize {
+        match &self.allocated_hosts {
+            Some(allocated_hosts) => allocated_hosts.borrow().len(),
+            None => 0,
+        }
+    }
+
+    pub fn allocated_ipv6_host_count(&self) -> usize {
+        match &self.allocated_ipv6_hosts {
+            Some(allocated_hosts) => allocated_hosts.borrow().len(),
+            None => 0,
+        }
+    }
+
+    pub fn free_host_count(&self) -> Option<u64> {
+        let subnet = self.subnet?;
+        let total_hosts = (1u64 << (32 - u32::from(subnet.prefix_len()))) - 2;
+
+        Some(total_hosts - self.excluded_host_count() - self.allocated_host_count() as u64)
+    }
+
+    pub fn free_ipv6_host_count(&self) -> Option<u128> {
+        let subnet = self.ipv6_subnet?;
+        let total_hosts = (1u128 << (128 - u32::from(subnet.prefix_len()))) - 1;
+
+        Some(total_hosts - self.allocated_ipv6_host_count() as u128)
+    }
+}
+
+fn parse_subnet(subnet: String) -> Result<Ipv4Net, LabBuilderError> {
+    subnet
+        .parse::<Ipv4Net>()
+        .map_err(|_| LabBuilderError::invalid_value("subnet", "Subnet is not a valid CIDR range."))
+        .and_then(|subnet| match subnet.prefix_len() {
+            0..=30 => Ok(subnet),
+            _ => Err(LabBuilderError::invalid_value(
+                "subnet",
+                "Subnet is smaller than /30. Networks smaller than /30 can't have multiple hosts.",
+            )),
+        })
+        .and_then(|subnet| {
+            let private_nets = [
+                "10.0.0.0/8".parse::<Ipv4Net>().unwrap(),
+                "172.16.0.0/12".parse::<Ipv4Net>().unwrap(),
+                "192.168.0.0/16".parse::<Ipv4Net>().unwrap(),
+            ];
+
+            if private_nets.iter().any(|priv_net| priv_net.contains(&subnet)) {
+                Ok(subnet)
+            } else {
+                Err(LabBuilderError::invalid_value(
+                    "subnet",
+                    "Subnet is not RFC 1918 compliant. Subnets must be in valid allocation for private networks.",
+                ))
+            }
+        })
+}
+
+/// 64-bit FNV-1a. `DefaultHasher` isn't guaranteed to give the same result across Rust releases,
+/// and hashed leases have to stay put between builds.
+pub(crate) fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Parses a single address, an inclusive `start-end` range or a CIDR block, all of which must
+/// lie within the network's IPv4 subnet.
+fn parse_range_in_subnet(
+    subnet: Option<Ipv4Net>,
+    range: &str,
+    path: &str,
+) -> Result<Ipv4Range, LabBuilderError> {
+    let subnet = subnet.ok_or_else(|| {
+        LabBuilderError::invalid_value(path, "Address exclusions require an IPv4 subnet.")
+    })?;
+
+    let parse_addr = |addr: &str| {
+        addr.trim().parse::<Ipv4Addr>().map_err(|_| {
+            LabBuilderError::invalid_value(
+                path,
+                "Not a valid IPv4 address, address range or CIDR range.",
+            )
+        })
+    };
+
+    let parsed = if let Some(dash) = range.find('-') {
+        Ipv4Range {
+            start: parse_addr(&range[..dash])?,
+            end: parse_addr(&range[dash + 1..])?,
+        }
+    } else if range.contains('/') {
+        let net = range.parse::<Ipv4Net>().map_err(|_| {
+            LabBuilderError::invalid_value(
+                path,
+                "Not a valid IPv4 address, address range or CIDR range.",
+            )
+        })?;
+        Ipv4Range {
+            start: net.network(),
+            end: net.broadcast(),
+        }
+    } else {
+        let addr = parse_addr(range)?;
+        Ipv4Range {
+            start: addr,
+            end: addr,
+        }
+    };
+
+    if parsed.start > parsed.end {
+        return Err(LabBuilderError::invalid_value(
+            path,
+            "Range start must not be after its end.",
+        ));
+    }
+
+    if !subnet.contains(&parsed.start) || !subnet.contains(&parsed.end) {
+        return Err(LabBuilderError::invalid_value(
+            path,
+            &format!("{} is not within subnet {}.", parsed, subnet.trunc()),
+        ));
+    }
+
+    // A CIDR range naturally spans the network and broadcast addresses, but an address or the
+    // ends of an address range must be hosts.
+    if !range.contains('/') {
+        let non_host = [parsed.start, parsed.end]
+            .iter()
+            .find(|&&addr| addr == subnet.network() || addr == subnet.broadcast())
+            .copied();
+        if let Some(addr) = non_host {
+            return Err(LabBuilderError::invalid_value(
+                path,
+                &format!(
+                    "Address {} is not a host address in subnet {}.",
+                    addr,
+                    subnet.trunc()
+                ),
+            ));
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Parses an address that must be a single host of the subnet, such as a gateway.
+fn parse_address_in_subnet(
+    subnet: Option<Ipv4Net>,
+    addr: &str,
+    path: &str,
+    not_single_msg: &str,
+) -> Result<Ipv4Addr, LabBuilderError> {
+    let range = parse_range_in_subnet(subnet, addr, path)?;
+    if range.start != range.end {
+        return Err(LabBuilderError::invalid_value(path, not_single_msg));
+    }
+    Ok(range.start)
+}
+
+fn parse_ipv6_subnet(subnet: String) -> Result<Ipv6Net, LabBuilderError> {
+    subnet
+        .parse::<Ipv6Net>()
+        .map_err(|_| {
+            LabBuilderError::invalid_value("ipv6_subnet", "Subnet is not a valid CIDR range.")
+        })
+        .and_then(|subnet| match subnet.prefix_len() {
+            0..=126 => Ok(subnet),
+            _ => Err(LabBuilderError::invalid_value(
+                "ipv6_subnet",
+                "Subnet is smaller than /126. Networks smaller than /126 can't have multiple hosts.",
+            )),
+        })
+        .and_then(|subnet| {
+            let unique_local_net = "fc00::/7".parse::<Ipv6Net>().unwrap();
+
+            if unique_local_net.contains(&subnet) {
+                Ok(subnet)
+            } else {
+                Err(LabBuilderError::invalid_value(
+                    "ipv6_subnet",
+                    "Subnet is not a unique local address range. IPv6 subnets must be within fc00::/7.",
+                ))
+            }
+        })
 }
 
 #[cfg(test)]
@@ -142,7 +717,7 @@ mod tests {
 
     #[test]
     fn parsing_network_without_name_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             type = "Internal"
             subnet = "192.168.0.0/24"
@@ -150,15 +725,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not read network name".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("name")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_name_that_is_not_a_string_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = 42
             type = "Internal"
@@ -167,15 +742,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not read network name as a string".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("name", "a string")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_without_type_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             subnet = "192.168.0.0/24"
@@ -183,15 +758,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not read network type for network: TestNet".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("type")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_type_that_is_not_a_string_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = 42
@@ -200,15 +775,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not read network type as a string for network: TestNet".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("type", "the name of a network type")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_type_that_is_not_valid_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "NotValid"
@@ -217,15 +792,18 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not parse network type as a valid type for network: TestNet. Valid types are: Public, Internal".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "type",
+                r#""NotValid" is not a valid option. Valid options are: Public, Internal, HostOnly, Nat"#
+            )
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_invalid_subnet_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Internal"
@@ -234,15 +812,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            "Could not parse subnet as a valid CIDR range for network: TestNet".to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value("subnet", "Subnet is not a valid CIDR range.")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_subnet_too_small_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Internal"
@@ -251,15 +829,18 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            r#"Network "TestNet" configured with a subnet smaller than /30. Networks smaller than /30 can't have multiple hosts."#.to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "subnet",
+                "Subnet is smaller than /30. Networks smaller than /30 can't have multiple hosts."
+            )
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_non_rfc1918_compliant_subnet_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Internal"
@@ -268,15 +849,18 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            r#"Subnet configured for network "TestNet" is not RFC 1918 compliant. Subnets must be in valid allocation for private networks."#.to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "subnet",
+                "Subnet is not RFC 1918 compliant. Subnets must be in valid allocation for private networks."
+            )
         );
         Ok(())
     }
 
     #[test]
     fn parsing_network_with_subnet_start_in_rfc1918_space_and_end_outside_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Internal"
@@ -285,15 +869,18 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            r#"Subnet configured for network "TestNet" is not RFC 1918 compliant. Subnets must be in valid allocation for private networks."#.to_string() 
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "subnet",
+                "Subnet is not RFC 1918 compliant. Subnets must be in valid allocation for private networks."
+            )
         );
         Ok(())
     }
 
     #[test]
     fn parsing_public_network_should_not_configure_subnet_or_available_hosts(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Public"
@@ -302,15 +889,44 @@ mod tests {
 
         let result = Network::from_toml(&input)?;
 
-        assert_eq!(result.subnet.is_none(), true);
-        assert_eq!(result.available_hosts.is_none(), true);
+        assert!(result.subnet.is_none());
+        assert!(result.available_hosts.is_none());
 
         Ok(())
     }
 
+    #[test]
+    fn parsing_public_network_with_bridge_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Public"
+            bridge = ["eth0", "wlan0"]
+            "#
+        .parse::<Value>()?;
+
+        let result = Network::from_toml(&input)?;
+
+        assert_eq!(result.bridge, vec!["eth0".to_string(), "wlan0".to_string()]);
+
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            bridge = "eth0"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value("bridge", "Only Public networks are bridged.")
+        );
+        Ok(())
+    }
+
     #[test]
     fn parsing_public_network_with_subnet_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "TestNet"
             type = "Public"
@@ -319,10 +935,487 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Network::from_toml(&input).unwrap_err().description(),
-            r#"Network "TestNet" is configured as a Public network and has a subnet configured. Public networks can't have configured subnets."#.to_string()
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "subnet",
+                "Public networks can't have configured subnets."
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_host_only_network_should_exclude_host_ip_from_leases(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "HostOnly"
+            subnet = "192.168.56.0/24"
+            "#
+        .parse::<Value>()?;
+
+        let result = Network::from_toml(&input)?;
+
+        assert_eq!(result.host_ip, Some("192.168.56.1".parse()?));
+        assert_eq!(result.gateway, None);
+        assert_eq!(result.free_host_count(), Some(253));
+        assert_eq!(result.get_address_lease(), Some("192.168.56.2".parse()?));
+
+        let input = r#"
+            name = "TestNet"
+            type = "Nat"
+            subnet = "10.0.5.0/24"
+            "#
+        .parse::<Value>()?;
+
+        let result = Network::from_toml(&input)?;
+
+        assert_eq!(result.host_ip, None);
+        assert_eq!(result.gateway, Some("10.0.5.1".parse()?));
+        assert_eq!(result.get_address_lease(), Some("10.0.5.2".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_misplaced_host_or_missing_subnet_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            host_ip = "192.168.0.1"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value("host_ip", "Only HostOnly networks have a host IP.")
+        );
+
+        let input = r#"
+            name = "TestNet"
+            type = "Nat"
+            ipv6_subnet = "fd00::/64"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("subnet")
+        );
+
+        let input = r#"
+            name = "TestNet"
+            type = "HostOnly"
+            subnet = "192.168.56.0/24"
+            host_ip = "192.168.57.1"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err().location().path,
+            "host_ip"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_ipv6_subnet_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00:1::/64"
+            "#
+        .parse::<Value>()?;
+
+        let result = Network::from_toml(&input)?;
+
+        assert_eq!(result.ipv6_subnet, Some("fd00:1::/64".parse::<Ipv6Net>()?));
+        assert_eq!(
+            result.get_lease(&Lease::default(), AllocationMode::Sequential, ""),
+            Ok(Lease {
+                ipv4: Some("192.168.0.1".parse()?),
+                ipv6: Some("fd00:1::1".parse()?),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_internal_network_with_only_ipv6_subnet_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            ipv6_subnet = "fd00::/126"
+            "#
+        .parse::<Value>()?;
+
+        let result = Network::from_toml(&input)?;
+
+        assert!(result.subnet.is_none());
+        assert_eq!(result.free_host_count(), None);
+        assert_eq!(result.free_ipv6_host_count(), Some(3));
+        assert_eq!(
+            result.get_lease(&Lease::default(), AllocationMode::Sequential, ""),
+            Ok(Lease {
+                ipv4: None,
+                ipv6: Some("fd00::1".parse()?),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_internal_network_without_any_subnet_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("subnet")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_non_ula_ipv6_subnet_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            ipv6_subnet = "2001:db8::/64"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ipv6_subnet",
+                "Subnet is not a unique local address range. IPv6 subnets must be within fc00::/7."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_ipv6_subnet_too_small_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            ipv6_subnet = "fd00::/127"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ipv6_subnet",
+                "Subnet is smaller than /126. Networks smaller than /126 can't have multiple hosts."
+            )
         );
+        Ok(())
+    }
+
+    #[test]
+    fn reserved_addresses_should_be_skipped_by_dynamic_leases(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+        network.reserve_address("192.168.0.1".parse()?)?;
+
+        assert_eq!(network.get_address_lease(), Some("192.168.0.2".parse()?));
+        assert_eq!(network.allocated_host_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn address_families_should_be_counted_separately(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/120"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+        network.reserve_ipv6_address("fd00::10".parse()?)?;
+        network.reserve_ipv6_address("fd00::11".parse()?)?;
+        network.reserve_address("192.168.0.1".parse()?)?;
+
+        assert_eq!(network.allocated_host_count(), 1);
+        assert_eq!(network.allocated_ipv6_host_count(), 2);
+        assert_eq!(network.free_host_count(), Some(253));
+        assert_eq!(network.free_ipv6_host_count(), Some(253));
+        Ok(())
+    }
 
+    #[test]
+    fn reserving_address_outside_subnet_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+
+        assert_eq!(
+            network
+                .reserve_address("192.168.1.10".parse()?)
+                .unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ip",
+                "Address 192.168.1.10 is not a host address in subnet 192.168.0.0/24."
+            )
+        );
+        assert_eq!(
+            network
+                .reserve_address("192.168.0.255".parse()?)
+                .unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ip",
+                "Address 192.168.0.255 is not a host address in subnet 192.168.0.0/24."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reserving_address_twice_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            ipv6_subnet = "fd00::/64"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+        network.reserve_ipv6_address("fd00::10".parse()?)?;
+
+        assert_eq!(
+            network
+                .reserve_ipv6_address("fd00::10".parse()?)
+                .unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ipv6",
+                r#"Address fd00::10 is already reserved on network "TestNet"."#
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn leasing_address_should_skip_gateway_reserved_and_dhcp_ranges(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            gateway = "192.168.0.1"
+            reserved = ["192.168.0.2-192.168.0.9", "192.168.0.8/29"]
+            dhcp_range = "192.168.0.100-192.168.0.199"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+
+        assert_eq!(network.get_address_lease(), Some("192.168.0.16".parse()?));
+        assert_eq!(network.free_host_count(), Some(254 - 15 - 100 - 1));
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_reserved_range_outside_subnet_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            reserved = ["192.168.0.10", "192.168.1.0-192.168.1.10"]
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "reserved[1]",
+                "192.168.1.0-192.168.1.10 is not within subnet 192.168.0.0/24."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_network_or_broadcast_address_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            gateway = "192.168.0.0"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "gateway",
+                "Address 192.168.0.0 is not a host address in subnet 192.168.0.0/24."
+            )
+        );
+
+        let input = r#"
+            name = "TestNet"
+            type = "HostOnly"
+            subnet = "192.168.0.0/24"
+            host_ip = "192.168.0.255"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "host_ip",
+                "Address 192.168.0.255 is not a host address in subnet 192.168.0.0/24."
+            )
+        );
+
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            reserved = ["192.168.0.0/28", "192.168.0.250-192.168.0.255"]
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "reserved[1]",
+                "Address 192.168.0.255 is not a host address in subnet 192.168.0.0/24."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_network_with_invalid_dhcp_range_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            dhcp_range = "192.168.0.200-192.168.0.100"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Network::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value("dhcp_range", "Range start must not be after its end.")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reserving_gateway_address_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            gateway = "192.168.0.1"
+            "#
+        .parse::<Value>()?;
+
+        let network = Network::from_toml(&input)?;
+
+        assert_eq!(
+            network.reserve_address("192.168.0.1".parse()?).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "ip",
+                r#"Address 192.168.0.1 is excluded from allocation on network "TestNet"."#
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_leases_should_be_stable_and_probe_past_collisions(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/64"
+            "#
+        .parse::<Value>()?;
+
+        let first = Network::from_toml(&input)?;
+        let second = Network::from_toml(&input)?;
+
+        let lease = first.get_lease(&Lease::default(), AllocationMode::Hashed, "Server/LAN/0")?;
+        second.get_lease(&Lease::default(), AllocationMode::Hashed, "Desktop/LAN/0")?;
+        let same_lease =
+            second.get_lease(&Lease::default(), AllocationMode::Hashed, "Server/LAN/0")?;
+        assert_eq!(lease, same_lease);
+
+        let collision =
+            first.get_lease(&Lease::default(), AllocationMode::Hashed, "Server/LAN/0")?;
+        assert_ne!(lease.ipv4, collision.ipv4);
+        assert_ne!(lease.ipv6, collision.ipv6);
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_leases_should_probe_past_excluded_addresses_and_fill_the_subnet(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/29"
+            "#;
+
+        let lease =
+            Network::from_toml(&input.parse::<Value>()?)?.get_hashed_address_lease("System")?;
+        let network =
+            Network::from_toml(&format!("{}gateway = \"{}\"", input, lease).parse::<Value>()?)?;
+
+        let probed_lease = network.get_hashed_address_lease("System")?;
+        assert_ne!(probed_lease, lease);
+        assert!(!network.is_excluded(probed_lease));
+
+        // The /29 has six hosts, one of them the gateway.
+        for _ in 0..4 {
+            network.get_hashed_address_lease("System")?;
+        }
+        assert_eq!(
+            network.get_hashed_address_lease("System"),
+            Err(LabBuilderError::HashedAddressTaken {
+                location: Location::new(""),
+                network: "TestNet".to_string(),
+                address: lease.to_string(),
+            })
+        );
         Ok(())
     }
 }
@@ -0,0 +1,559 @@
+use crate::indentation_aware_string_builder::{IndentationAwareStringBuilder, IndentationType};
+use crate::network::NetworkType;
+use crate::resolved_scenario::ResolvedScenario;
+
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Plan {
+    pub scenario: String,
+    pub networks: Vec<NetworkPlan>,
+    pub systems: Vec<SystemPlan>,
+    pub firewall: Option<FirewallPlan>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NetworkPlan {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub network_type: String,
+    pub subnet: Option<String>,
+    pub ipv6_subnet: Option<String>,
+    pub gateway: Option<String>,
+    pub host_ip: Option<String>,
+    pub reserved: Vec<String>,
+    pub dhcp_range: Option<String>,
+    pub bridge: Vec<String>,
+    pub allocated_hosts: usize,
+    pub allocated_ipv6_hosts: usize,
+    pub free_hosts: Option<u64>,
+    pub free_ipv6_hosts: Option<u128>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SystemPlan {
+    pub name: String,
+    pub base_box: String,
+    pub router: bool,
+    pub nics: Vec<NicPlan>,
+    pub routes: Vec<String>,
+}
+
+/// The firewall rules routers apply, with a matrix showing which of them can match traffic
+/// between each pair of networks with addresses.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FirewallPlan {
+    pub tool: String,
+    pub policy: String,
+    pub rules: Vec<String>,
+    pub networks: Vec<String>,
+    /// For each source network, the numbers of the rules that can match traffic to each
+    /// destination network, in the order of `networks`. Numbers start at 1.
+    pub matrix: Vec<Vec<Vec<usize>>>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NicPlan {
+    pub network: String,
+    pub address: Option<String>,
+    pub ipv6_address: Option<String>,
+    /// The netmask of a static IP on a public network.
+    pub netmask: Option<String>,
+    pub mac: String,
+}
+
+impl Plan {
+    pub fn from_scenario(scenario: &ResolvedScenario) -> Plan {
+        let networks = scenario
+            .networks
+            .iter()
+            .map(|net| NetworkPlan {
+                name: net.name.to_string(),
+                network_type: format!("{:?}", net.network_type),
+                subnet: net.subnet.map(|subnet| subnet.trunc().to_string()),
+                ipv6_subnet: net.ipv6_subnet.map(|subnet| subnet.trunc().to_string()),
+                gateway: net.gateway.map(|gateway| gateway.to_string()),
+                host_ip: net.host_ip.map(|host_ip| host_ip.to_string()),
+                reserved: net.reserved.iter().map(|range| range.to_string()).collect(),
+                dhcp_range: net.dhcp_range.map(|range| range.to_string()),
+                bridge: net.bridge.clone(),
+                allocated_hosts: net.allocated_host_count(),
+                allocated_ipv6_hosts: net.allocated_ipv6_host_count(),
+                free_hosts: net.free_host_count(),
+                free_ipv6_hosts: net.free_ipv6_host_count(),
+            })
+            .collect();
+
+        let systems = scenario
+            .systems
+            .iter()
+            .map(|system| SystemPlan {
+                name: system.name.to_string(),
+                base_box: system.base_box.to_string(),
+                router: system.router,
+                nics: system
+                    .nics
+                    .iter()
+                    .map(|nic| NicPlan {
+                        network: nic.network.name.to_string(),
+                        mac: nic.mac.to_string(),
+                        address: nic
+                            .lease
+                            .and_then(|lease| lease.ipv4)
+                            .or(nic.static_ip)
+                            .map(|addr| addr.to_string()),
+                        ipv6_address: nic
+                            .lease
+                            .and_then(|lease| lease.ipv6)
+                            .map(|addr| addr.to_string()),
+                        netmask: nic.netmask.map(|netmask| netmask.to_string()),
+                    })
+                    .collect(),
+                routes: system
+                    .routes
+                    .iter()
+                    .map(|route| route.to_string())
+                    .collect(),
+            })
+            .collect();
+
+        Plan {
+            scenario: scenario.name.to_string(),
+            networks,
+            systems,
+            firewall: FirewallPlan::from_scenario(scenario),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut builder = IndentationAwareStringBuilder::new();
+        builder
+            .with_indentation_type(IndentationType::Spaces)
+            .with_tab_size(4);
+
+        builder.add(format!("Scenario: {}", self.scenario));
+        builder.add("".to_string());
+
+        builder.add("Networks:".to_string());
+        builder.increase_indentation();
+        for net in self.networks.iter() {
+            builder.add(format!("{} ({})", net.name, net.network_type));
+            builder.increase_indentation();
+            if let Some(subnet) = &net.subnet {
+                builder.add(format!("subnet: {}", subnet));
+            }
+            if let Some(ipv6_subnet) = &net.ipv6_subnet {
+                builder.add(format!("ipv6 subnet: {}", ipv6_subnet));
+            }
+            if let Some(gateway) = &net.gateway {
+                builder.add(format!("gateway: {}", gateway));
+            }
+            if let Some(host_ip) = &net.host_ip {
+                builder.add(format!("host ip: {}", host_ip));
+            }
+            if !net.reserved.is_empty() {
+                builder.add(format!("reserved: {}", net.reserved.join(", ")));
+            }
+            if let Some(dhcp_range) = &net.dhcp_range {
+                builder.add(format!("dhcp range: {}", dhcp_range));
+            }
+            if !net.bridge.is_empty() {
+                builder.add(format!("bridge: {}", net.bridge.join(", ")));
+            }
+            if net.subnet.is_some() {
+                builder.add(format!("allocated hosts: {}", net.allocated_hosts));
+            }
+            if let Some(free_hosts) = net.free_hosts {
+                builder.add(format!("free hosts: {}", free_hosts));
+            }
+            if net.ipv6_subnet.is_some() {
+                builder.add(format!(
+                    "allocated ipv6 hosts: {}",
+                    net.allocated_ipv6_hosts
+                ));
+            }
+            if let Some(free_ipv6_hosts) = net.free_ipv6_hosts {
+                builder.add(format!("free ipv6 hosts: {}", free_ipv6_hosts));
+            }
+            builder.decrease_indentation();
+        }
+        builder.decrease_indentation();
+        builder.add("".to_string());
+
+        builder.add("Systems:".to_string());
+        builder.increase_indentation();
+        for system in self.systems.iter() {
+            builder.add(system.name.to_string());
+            builder.increase_indentation();
+            builder.add(format!("base box: {}", system.base_box));
+            if system.router {
+                builder.add("router: forwards traffic between its networks".to_string());
+            }
+            builder.add("NICs:".to_string());
+            builder.increase_indentation();
+            for (index, nic) in system.nics.iter().enumerate() {
+                let netmask = nic
+                    .netmask
+                    .as_ref()
+                    .map(|netmask| format!("netmask {}", netmask));
+                let addresses: Vec<&str> = nic
+                    .address
+                    .iter()
+                    .chain(netmask.iter())
+                    .chain(nic.ipv6_address.iter())
+                    .map(String::as_str)
+                    .collect();
+                match addresses.is_empty() {
+                    true => builder.add(format!("nic{}: {} ({})", index, nic.network, nic.mac)),
+                    false => builder.add(format!(
+                        "nic{}: {} {} ({})",
+                        index,
+                        nic.network,
+                        addresses.join(" "),
+                        nic.mac
+                    )),
+                }
+            }
+            builder.decrease_indentation();
+            if !system.routes.is_empty() {
+                builder.add("routes:".to_string());
+                builder.increase_indentation();
+                for route in system.routes.iter() {
+                    builder.add(route.to_string());
+                }
+                builder.decrease_indentation();
+            }
+            builder.decrease_indentation();
+        }
+        builder.decrease_indentation();
+
+        if let Some(firewall) = &self.firewall {
+            builder.add("".to_string());
+            firewall.add_text(&mut builder);
+        }
+
+        builder.build_string()
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl FirewallPlan {
+    /// Plans the firewall, unless it forwards everything.
+    fn from_scenario(scenario: &ResolvedScenario) -> Option<FirewallPlan> {
+        if !scenario.firewall.is_active() {
+            return None;
+        }
+
+        let networks: Vec<&str> = scenario
+            .networks
+            .iter()
+            .filter(|net| net.network_type != NetworkType::Public)
+            .map(|net| net.name.as_str())
+            .collect();
+
+        // Whether an endpoint of a rule covers traffic on a network: it's unset, names the
+        // network, or names a system with an address on it.
+        let covers = |endpoint: &Option<String>, network: &str| match endpoint {
+            None => true,
+            Some(name) if name == network => true,
+            Some(name) => scenario.systems.iter().any(|system| {
+                &system.name == name
+                    && system
+                        .nics
+                        .iter()
+                        .any(|nic| nic.network.name == network && nic.lease.is_some())
+            }),
+        };
+
+        let matrix = networks
+            .iter()
+            .map(|source| {
+                networks
+                    .iter()
+                    .map(|destination| {
+                        (1..=scenario.firewall.rules.len())
+                            .filter(|&number| {
+                                let rule = &scenario.firewall.rules[number - 1];
+                                covers(&rule.source, source)
+                                    && covers(&rule.destination, destination)
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Some(FirewallPlan {
+            tool: scenario.firewall.tool.to_string(),
+            policy: scenario.firewall.policy.to_string(),
+            rules: scenario
+                .firewall
+                .rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect(),
+            networks: networks.iter().map(|name| name.to_string()).collect(),
+            matrix,
+        })
+    }
+
+    /// Adds the rules and the matrix, whose rows are sources and columns destinations. Traffic
+    /// within a network isn't forwarded, so those cells are marked local.
+    fn add_text(&self, builder: &mut IndentationAwareStringBuilder) {
+        builder.add(format!(
+            "Firewall ({}, default {}):",
+            self.tool, self.policy
+        ));
+        builder.increase_indentation();
+
+        if !self.rules.is_empty() {
+            builder.add("rules:".to_string());
+            builder.increase_indentation();
+            for (index, rule) in self.rules.iter().enumerate() {
+                builder.add(format!("{}: {}", index + 1, rule));
+            }
+            builder.decrease_indentation();
+        }
+
+        if !self.networks.is_empty() {
+            let mut rows = vec![std::iter::once(r"from \ to".to_string())
+                .chain(self.networks.iter().cloned())
+                .collect::<Vec<String>>()];
+            for (source, cells) in self.networks.iter().zip(self.matrix.iter()) {
+                let mut row = vec![source.to_string()];
+                for (destination, rules) in self.networks.iter().zip(cells.iter()) {
+                    row.push(match rules.as_slice() {
+                        _ if source == destination => "local".to_string(),
+                        [] => "-".to_string(),
+                        rules => rules
+                            .iter()
+                            .map(|number| number.to_string())
+                            .collect::<Vec<String>>()
+                            .join(","),
+                    });
+                }
+                rows.push(row);
+            }
+
+            let widths: Vec<usize> = (0..rows[0].len())
+                .map(|column| {
+                    rows.iter()
+                        .map(|row| row[column].chars().count())
+                        .max()
+                        .unwrap_or_default()
+                })
+                .collect();
+
+            builder.add("matrix (rules matching traffic from row to column):".to_string());
+            builder.increase_indentation();
+            for row in rows.iter() {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(widths.iter())
+                    .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+                    .collect();
+                builder.add(line.join("  ").trim_end().to_string());
+            }
+            builder.decrease_indentation();
+        }
+
+        builder.decrease_indentation();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    fn planned_scenario() -> Result<ResolvedScenario, std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = [
+                { name = "LAN" },
+                { name = "WAN", ip = "192.0.2.10", netmask = "255.255.255.0" },
+            ]
+            base_box = "Windows 10"
+
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.1/24"
+            ipv6_subnet = "fd00::/64"
+            gateway = "192.168.0.254"
+            reserved = ["192.168.0.200-192.168.0.209"]
+
+            [[networks]]
+            name = "WAN"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        Ok(Scenario::from_toml(&input)?.resolve()?)
+    }
+
+    #[test]
+    fn plan_for_simple_scenario_counts_allocated_and_free_hosts(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let plan = Plan::from_scenario(&planned_scenario()?);
+
+        assert_eq!(plan.networks[0].subnet, Some("192.168.0.0/24".to_string()));
+        assert_eq!(plan.networks[0].allocated_hosts, 2);
+        assert_eq!(plan.networks[0].free_hosts, Some(241));
+        assert_eq!(plan.networks[1].subnet, None);
+        assert_eq!(plan.networks[1].free_hosts, None);
+        assert_eq!(plan.networks[0].free_ipv6_hosts, Some((1 << 64) - 3));
+        assert_eq!(plan.networks[1].free_ipv6_hosts, None);
+
+        assert_eq!(
+            plan.systems[0].nics,
+            vec![
+                NicPlan {
+                    network: "LAN".to_string(),
+                    address: Some("192.168.0.1".to_string()),
+                    ipv6_address: Some("fd00::1".to_string()),
+                    netmask: None,
+                    mac: "62:09:56:58:f8:be".to_string(),
+                },
+                NicPlan {
+                    network: "WAN".to_string(),
+                    address: Some("192.0.2.10".to_string()),
+                    ipv6_address: None,
+                    netmask: Some("255.255.255.0".to_string()),
+                    mac: "32:cc:05:81:cb:bb".to_string(),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn text_output_for_simple_scenario_works() -> Result<(), std::boxed::Box<dyn std::error::Error>>
+    {
+        let plan = Plan::from_scenario(&planned_scenario()?);
+
+        let expected = r#"Scenario: Test scenario
+
+Networks:
+    LAN (Internal)
+        subnet: 192.168.0.0/24
+        ipv6 subnet: fd00::/64
+        gateway: 192.168.0.254
+        reserved: 192.168.0.200-192.168.0.209
+        allocated hosts: 2
+        free hosts: 241
+        allocated ipv6 hosts: 2
+        free ipv6 hosts: 18446744073709551613
+    WAN (Public)
+
+Systems:
+    Desktop
+        base box: Windows 10
+        NICs:
+            nic0: LAN 192.168.0.1 fd00::1 (62:09:56:58:f8:be)
+            nic1: WAN 192.0.2.10 netmask 255.255.255.0 (32:cc:05:81:cb:bb)
+    Server
+        base box: Debian
+        NICs:
+            nic0: LAN 192.168.0.2 fd00::2 (ea:fc:b7:86:77:d1)"#;
+
+        assert_eq!(plan.to_text(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn json_output_for_simple_scenario_works() -> Result<(), std::boxed::Box<dyn std::error::Error>>
+    {
+        let plan = Plan::from_scenario(&planned_scenario()?);
+
+        let json = serde_json::from_str::<serde_json::Value>(&plan.to_json()?)?;
+
+        assert_eq!(json["scenario"], "Test scenario");
+        assert_eq!(json["networks"][0]["type"], "Internal");
+        assert_eq!(json["networks"][0]["free_hosts"], 241);
+        assert_eq!(
+            json["networks"][0]["free_ipv6_hosts"],
+            18446744073709551613u64
+        );
+        assert_eq!(json["networks"][0]["gateway"], "192.168.0.254");
+        assert_eq!(json["systems"][1]["nics"][0]["address"], "192.168.0.2");
+        assert_eq!(json["systems"][1]["nics"][0]["ipv6_address"], "fd00::2");
+        Ok(())
+    }
+
+    #[test]
+    fn text_output_should_include_firewall_rule_matrix(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            firewall_policy = "Drop"
+
+            [[systems]]
+            name = "Router"
+            networks = ["LAN", "DMZ"]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Web"
+            networks = ["DMZ"]
+            base_box = "Debian"
+
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "10.0.1.0/24"
+
+            [[networks]]
+            name = "DMZ"
+            type = "Internal"
+            subnet = "10.0.3.0/24"
+
+            [[firewall_rules]]
+            source = "LAN"
+            action = "Accept"
+
+            [[firewall_rules]]
+            source = "Web"
+            destination = "LAN"
+            protocol = "Tcp"
+            port = 445
+            action = "Accept"
+        "#
+        .parse::<Value>()?;
+
+        let plan = Plan::from_scenario(&Scenario::from_toml(&input)?.resolve()?);
+
+        let expected = r#"Firewall (nftables, default drop):
+    rules:
+        1: LAN -> any any accept
+        2: Web -> LAN tcp/445 accept
+    matrix (rules matching traffic from row to column):
+        from \ to  LAN    DMZ
+        LAN        local  1
+        DMZ        2      local"#;
+
+        assert!(plan.to_text().ends_with(expected));
+        assert_eq!(
+            plan.firewall.map(|firewall| firewall.matrix),
+            Some(vec![vec![vec![1], vec![1]], vec![vec![2], vec![]]])
+        );
+        Ok(())
+    }
+}
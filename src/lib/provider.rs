@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+/// The hypervisor a lab is built for. Backends use it to pick provider-specific settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(expecting = "the name of a provider")]
+pub enum Provider {
+    #[default]
+    VirtualBox,
+    Libvirt,
+}
+
+impl Provider {
+    pub fn from_name(name: &str) -> Option<Provider> {
+        match name {
+            "virtualbox" => Some(Provider::VirtualBox),
+            "libvirt" => Some(Provider::Libvirt),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::LabBuilderError;
+
+use serde::Deserialize;
+
+use std::path::PathBuf;
+
+/// A step run on a system after it boots. Systems run their provisioners in the order declared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Provisioner {
+    Shell(Script),
+    /// A PowerShell script for Windows boxes.
+    PowerShell(Script),
+    /// An Ansible playbook, run from the host against the system.
+    Ansible {
+        playbook: PathBuf,
+    },
+    /// A local file uploaded to the system.
+    File {
+        source: PathBuf,
+        destination: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Script {
+    Inline(String),
+    Path(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(expecting = "the name of a provisioner type")]
+pub enum ProvisionerType {
+    Shell,
+    PowerShell,
+    Ansible,
+    File,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a provisioner table")]
+pub struct ProvisionerDefinition {
+    #[serde(rename = "type")]
+    pub provisioner_type: ProvisionerType,
+    pub inline: Option<String>,
+    pub path: Option<String>,
+    pub playbook: Option<String>,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+}
+
+impl Provisioner {
+    pub fn from_definition(
+        definition: ProvisionerDefinition,
+    ) -> Result<Provisioner, LabBuilderError> {
+        let unused_field = match definition.provisioner_type {
+            ProvisionerType::Shell | ProvisionerType::PowerShell => definition
+                .playbook
+                .as_ref()
+                .map(|_| "playbook")
+                .or_else(|| definition.source.as_ref().map(|_| "source"))
+                .or_else(|| definition.destination.as_ref().map(|_| "destination")),
+            ProvisionerType::Ansible => definition
+                .inline
+                .as_ref()
+                .map(|_| "inline")
+                .or_else(|| definition.path.as_ref().map(|_| "path"))
+                .or_else(|| definition.source.as_ref().map(|_| "source"))
+                .or_else(|| definition.destination.as_ref().map(|_| "destination")),
+            ProvisionerType::File => definition
+                .inline
+                .as_ref()
+                .map(|_| "inline")
+                .or_else(|| definition.path.as_ref().map(|_| "path"))
+                .or_else(|| definition.playbook.as_ref().map(|_| "playbook")),
+        };
+        if let Some(field) = unused_field {
+            return Err(LabBuilderError::invalid_value(
+                field,
+                &format!(
+                    r#"{:?} provisioners don't use "{}"."#,
+                    definition.provisioner_type, field
+                ),
+            ));
+        }
+
+        match definition.provisioner_type {
+            ProvisionerType::Shell => Ok(Provisioner::Shell(script(definition)?)),
+            ProvisionerType::PowerShell => Ok(Provisioner::PowerShell(script(definition)?)),
+            ProvisionerType::Ansible => Ok(Provisioner::Ansible {
+                playbook: definition
+                    .playbook
+                    .map(PathBuf::from)
+                    .ok_or_else(|| LabBuilderError::missing_field("playbook"))?,
+            }),
+            ProvisionerType::File => Ok(Provisioner::File {
+                source: definition
+                    .source
+                    .map(PathBuf::from)
+                    .ok_or_else(|| LabBuilderError::missing_field("source"))?,
+                destination: definition
+                    .destination
+                    .ok_or_else(|| LabBuilderError::missing_field("destination"))?,
+            }),
+        }
+    }
+
+    /// The local file the provisioner reads, if any, along with the field naming it.
+    pub fn local_file_mut(&mut self) -> Option<(&'static str, &mut PathBuf)> {
+        match self {
+            Provisioner::Shell(Script::Path(path))
+            | Provisioner::PowerShell(Script::Path(path)) => Some(("path", path)),
+            Provisioner::Shell(Script::Inline(_)) | Provisioner::PowerShell(Script::Inline(_)) => {
+                None
+            }
+            Provisioner::Ansible { playbook } => Some(("playbook", playbook)),
+            Provisioner::File { source, .. } => Some(("source", source)),
+        }
+    }
+}
+
+fn script(definition: ProvisionerDefinition) -> Result<Script, LabBuilderError> {
+    match (definition.inline, definition.path) {
+        (Some(inline), None) => Ok(Script::Inline(inline)),
+        (None, Some(path)) => Ok(Script::Path(PathBuf::from(path))),
+        (Some(_), Some(_)) => Err(LabBuilderError::invalid_value(
+            "path",
+            "Provisioners run either an inline script or a script file, not both.",
+        )),
+        (None, None) => Err(LabBuilderError::missing_field("inline")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::schema;
+
+    use toml::Value;
+
+    fn parse(input: &str) -> Result<Provisioner, std::boxed::Box<dyn std::error::Error>> {
+        let value = input.parse::<Value>()?;
+        Ok(Provisioner::from_definition(schema::from_value(&value)?)?)
+    }
+
+    #[test]
+    fn parsing_provisioners_should_work() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        assert_eq!(
+            parse(r#"type = "Shell""#).unwrap_err().to_string(),
+            LabBuilderError::missing_field("inline").to_string()
+        );
+        assert_eq!(
+            parse(
+                r#"
+                type = "PowerShell"
+                path = "scripts/dc.ps1"
+                "#
+            )?,
+            Provisioner::PowerShell(Script::Path(PathBuf::from("scripts/dc.ps1")))
+        );
+        assert_eq!(
+            parse(
+                r#"
+                type = "File"
+                source = "files/motd"
+                destination = "/etc/motd"
+                "#
+            )?,
+            Provisioner::File {
+                source: PathBuf::from("files/motd"),
+                destination: "/etc/motd".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_provisioner_with_fields_of_other_type_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            type = "Ansible"
+            playbook = "site.yml"
+            inline = "echo hello"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Provisioner::from_definition(schema::from_value(&input)?).unwrap_err(),
+            LabBuilderError::invalid_value("inline", r#"Ansible provisioners don't use "inline"."#)
+        );
+
+        let input = r#"
+            type = "Shell"
+            inline = "echo hello"
+            path = "hello.sh"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Provisioner::from_definition(schema::from_value(&input)?).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "path",
+                "Provisioners run either an inline script or a script file, not both."
+            )
+        );
+        Ok(())
+    }
+}
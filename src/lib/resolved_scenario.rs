@@ -0,0 +1,93 @@
+use crate::firewall::Firewall;
+use crate::forwarded_port::ForwardedPort;
+use crate::hardware::Hardware;
+use crate::mac_address::MacAddress;
+use crate::name_resolution::NameResolution;
+use crate::network::{Lease, Network};
+use crate::provider::Provider;
+use crate::provisioner::Provisioner;
+use crate::routing::Route;
+use crate::synced_folder::SyncedFolder;
+
+use std::net::Ipv4Addr;
+use std::rc::Rc;
+
+/// A scenario whose networking has been fully configured by `Scenario::resolve`. Generators
+/// only accept this type, so they can rely on every NIC of an internal network having a lease.
+/// Its fields can only be read outside the crate, so `Scenario::resolve` is the only way to
+/// build one.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedScenario {
+    pub(crate) name: String,
+    pub(crate) provider: Provider,
+    /// The network Ansible and other management tools reach systems on, if one was chosen.
+    pub(crate) management_network: Option<String>,
+    /// The domain every system is named under, such as `desktop.lan.lab`.
+    pub(crate) domain: String,
+    pub(crate) name_resolution: Option<NameResolution>,
+    /// Filtering applied by every router to the traffic it forwards.
+    pub(crate) firewall: Firewall,
+    pub(crate) networks: Vec<Rc<Network>>,
+    pub(crate) systems: Vec<ResolvedSystem>,
+}
+
+impl ResolvedScenario {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    pub fn management_network(&self) -> Option<&str> {
+        self.management_network.as_deref()
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn name_resolution(&self) -> Option<NameResolution> {
+        self.name_resolution
+    }
+
+    pub fn firewall(&self) -> &Firewall {
+        &self.firewall
+    }
+
+    pub fn networks(&self) -> &[Rc<Network>] {
+        &self.networks
+    }
+
+    pub fn systems(&self) -> &[ResolvedSystem] {
+        &self.systems
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResolvedSystem {
+    pub name: String,
+    pub base_box: String,
+    pub tags: Vec<String>,
+    pub hardware: Hardware,
+    pub provisioners: Vec<Provisioner>,
+    pub forwarded_ports: Vec<ForwardedPort>,
+    pub synced_folders: Vec<SyncedFolder>,
+    pub router: bool,
+    pub lab_names: bool,
+    /// Static routes to networks the system isn't attached to, set by `Scenario::resolve`.
+    pub routes: Vec<Route>,
+    pub nics: Vec<ResolvedNic>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResolvedNic {
+    pub network: Rc<Network>,
+    pub lease: Option<Lease>,
+    /// The address given to a NIC on a public network, which uses DHCP when unset. It isn't a
+    /// lease, as the subnet of a public network is up to the network the host is on.
+    pub static_ip: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    pub mac: MacAddress,
+}
@@ -0,0 +1,403 @@
+use crate::error::{LabBuilderError, Location};
+use crate::indentation_aware_string_builder::IndentationAwareStringBuilder;
+use crate::network::{Lease, Network};
+use crate::resolved_scenario::ResolvedSystem;
+
+use ipnet::IpNet;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+/// A static route sending traffic for a network that isn't attached to a system through a router
+/// on one that is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub destination: IpNet,
+    pub gateway: IpAddr,
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} via {}", self.destination, self.gateway)
+    }
+}
+
+/// Works out the routes of every system, in order. Only networks with a router attached take
+/// part in routing: every system on one of them gets a route to each of the others, through the
+/// first router on the shortest path there. Networks without a router are left isolated.
+///
+/// It's an error for a routed network to be unreachable from a system, or for the shortest paths
+/// to it to start at more than one router, as the system could only use one of them.
+pub fn compute_routes(
+    networks: &[Rc<Network>],
+    systems: &[ResolvedSystem],
+) -> Result<Vec<Vec<Route>>, LabBuilderError> {
+    let attached: Vec<Vec<usize>> = systems
+        .iter()
+        .map(|system| attached_networks(networks, system))
+        .collect();
+    let routed: Vec<bool> = (0..networks.len())
+        .map(|net| {
+            systems
+                .iter()
+                .zip(attached.iter())
+                .any(|(system, nets)| system.router && nets.contains(&net))
+        })
+        .collect();
+
+    let mut all_routes = Vec::new();
+    for (index, system) in systems.iter().enumerate() {
+        let mut routes = Vec::new();
+        let on_routed_network = attached[index].iter().any(|&net| routed[net]);
+
+        for destination in (0..networks.len()).filter(|&net| routed[net]) {
+            if !on_routed_network || attached[index].contains(&destination) {
+                continue;
+            }
+
+            let distances = distances_to(destination, systems, &attached, networks.len());
+            let first_hops = first_hops(index, systems, &attached, &distances);
+
+            let (router, local_network) = match first_hops.as_slice() {
+                [] => {
+                    return Err(LabBuilderError::NoRoute {
+                        location: Location::new(&format!("systems[{}]", index)),
+                        system: system.name.to_string(),
+                        network: networks[destination].name.to_string(),
+                    })
+                }
+                [first_hop] => *first_hop,
+                _ => {
+                    return Err(LabBuilderError::AmbiguousRoute {
+                        location: Location::new(&format!("systems[{}]", index)),
+                        system: system.name.to_string(),
+                        network: networks[destination].name.to_string(),
+                        routers: first_hops
+                            .iter()
+                            .map(|&(router, _)| systems[router].name.to_string())
+                            .collect(),
+                    })
+                }
+            };
+
+            let net = &networks[destination];
+            let gateway = lease_on(&systems[router], &networks[local_network]);
+            let own_lease = lease_on(system, &networks[local_network]);
+            if let (Some(subnet), Some(gateway), Some(_)) = (
+                net.subnet,
+                gateway.and_then(|lease| lease.ipv4),
+                own_lease.and_then(|lease| lease.ipv4),
+            ) {
+                routes.push(Route {
+                    destination: IpNet::V4(subnet.trunc()),
+                    gateway: IpAddr::V4(gateway),
+                });
+            }
+            if let (Some(subnet), Some(gateway), Some(_)) = (
+                net.ipv6_subnet,
+                gateway.and_then(|lease| lease.ipv6),
+                own_lease.and_then(|lease| lease.ipv6),
+            ) {
+                routes.push(Route {
+                    destination: IpNet::V6(subnet.trunc()),
+                    gateway: IpAddr::V6(gateway),
+                });
+            }
+        }
+
+        all_routes.push(routes);
+    }
+
+    Ok(all_routes)
+}
+
+/// A shell script run on every boot, enabling forwarding on routers and adding static routes.
+/// Returns nothing for systems that need neither.
+pub fn provisioning_script(system: &ResolvedSystem) -> Option<String> {
+    if !system.router && system.routes.is_empty() {
+        return None;
+    }
+
+    let mut builder = IndentationAwareStringBuilder::new();
+    if system.router {
+        builder.add("sysctl -w net.ipv4.ip_forward=1".to_string());
+        builder.add("sysctl -w net.ipv6.conf.all.forwarding=1".to_string());
+    }
+    for route in system.routes.iter() {
+        match route.destination {
+            IpNet::V4(_) => builder.add(format!("ip route replace {}", route)),
+            IpNet::V6(_) => builder.add(format!("ip -6 route replace {}", route)),
+        }
+    }
+
+    Some(builder.build_string())
+}
+
+/// Indices of the networks of a system that have addresses, which are the only ones it can route
+/// through.
+fn attached_networks(networks: &[Rc<Network>], system: &ResolvedSystem) -> Vec<usize> {
+    let mut attached = Vec::new();
+    for nic in system.nics.iter().filter(|nic| nic.lease.is_some()) {
+        if let Some(index) = networks.iter().position(|net| net.name == nic.network.name) {
+            if !attached.contains(&index) {
+                attached.push(index);
+            }
+        }
+    }
+    attached
+}
+
+/// The number of routers traffic crosses to get from each network to the destination, found by
+/// searching outwards from the destination. Unreachable networks have no distance.
+fn distances_to(
+    destination: usize,
+    systems: &[ResolvedSystem],
+    attached: &[Vec<usize>],
+    network_count: usize,
+) -> Vec<Option<usize>> {
+    let mut distances = vec![None; network_count];
+    distances[destination] = Some(0);
+
+    let mut queue = VecDeque::new();
+    queue.push_back(destination);
+    while let Some(net) = queue.pop_front() {
+        let distance = distances[net].unwrap_or_default();
+        for (system, nets) in systems.iter().zip(attached.iter()) {
+            if !system.router || !nets.contains(&net) {
+                continue;
+            }
+            for &neighbour in nets.iter() {
+                if distances[neighbour].is_none() {
+                    distances[neighbour] = Some(distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    distances
+}
+
+/// Every router and local network pair a system could send traffic through on a shortest path to
+/// the destination the distances were measured from.
+fn first_hops(
+    index: usize,
+    systems: &[ResolvedSystem],
+    attached: &[Vec<usize>],
+    distances: &[Option<usize>],
+) -> Vec<(usize, usize)> {
+    let mut best = None;
+    let mut hops = Vec::new();
+
+    for &local_network in attached[index].iter() {
+        for (router, nets) in attached.iter().enumerate() {
+            if router == index || !systems[router].router || !nets.contains(&local_network) {
+                continue;
+            }
+
+            let cost = nets
+                .iter()
+                .filter(|&&net| net != local_network)
+                .filter_map(|&net| distances[net])
+                .min();
+            let cost = match cost {
+                Some(cost) => cost,
+                None => continue,
+            };
+
+            if best.is_none_or(|best| cost < best) {
+                best = Some(cost);
+                hops.clear();
+            }
+            if best == Some(cost) {
+                hops.push((router, local_network));
+            }
+        }
+    }
+
+    hops
+}
+
+fn lease_on(system: &ResolvedSystem, network: &Network) -> Option<Lease> {
+    system
+        .nics
+        .iter()
+        .find(|nic| nic.network.name == network.name)
+        .and_then(|nic| nic.lease)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::resolved_scenario::ResolvedScenario;
+    use crate::scenario::Scenario;
+
+    use toml::Value;
+
+    fn resolve(input: &str) -> Result<ResolvedScenario, LabBuilderError> {
+        Scenario::from_toml(&input.parse::<Value>().unwrap())?.resolve()
+    }
+
+    const NETWORKS: &str = r#"
+        [[networks]]
+        name = "LAN"
+        type = "Internal"
+        subnet = "10.0.1.0/24"
+
+        [[networks]]
+        name = "Transit"
+        type = "Internal"
+        subnet = "10.0.2.0/24"
+
+        [[networks]]
+        name = "DMZ"
+        type = "Internal"
+        subnet = "10.0.3.0/24"
+    "#;
+
+    #[test]
+    fn routes_should_go_through_first_router_on_path(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario = resolve(&format!(
+            r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = [{{ name = "LAN", ip = "10.0.1.10" }}]
+            base_box = "Debian"
+
+            [[systems]]
+            name = "Inner Router"
+            networks = [{{ name = "LAN", ip = "10.0.1.1" }}, {{ name = "Transit", ip = "10.0.2.1" }}]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Outer Router"
+            networks = [{{ name = "Transit", ip = "10.0.2.2" }}, {{ name = "DMZ", ip = "10.0.3.1" }}]
+            base_box = "VyOS"
+            router = true
+            {}
+            "#,
+            NETWORKS
+        ))?;
+
+        let routes: Vec<Vec<String>> = scenario
+            .systems
+            .iter()
+            .map(|system| system.routes.iter().map(Route::to_string).collect())
+            .collect();
+
+        assert_eq!(
+            routes,
+            vec![
+                vec![
+                    "10.0.2.0/24 via 10.0.1.1".to_string(),
+                    "10.0.3.0/24 via 10.0.1.1".to_string()
+                ],
+                vec!["10.0.3.0/24 via 10.0.2.2".to_string()],
+                vec!["10.0.1.0/24 via 10.0.2.1".to_string()],
+            ]
+        );
+        assert_eq!(
+            provisioning_script(&scenario.systems[1]),
+            Some(
+                "sysctl -w net.ipv4.ip_forward=1
+sysctl -w net.ipv6.conf.all.forwarding=1
+ip route replace 10.0.3.0/24 via 10.0.2.2"
+                    .to_string()
+            )
+        );
+        assert!(provisioning_script(&scenario.systems[0]).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn routing_without_path_should_fail_with_msg() {
+        let error = resolve(&format!(
+            r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Router"
+            networks = ["LAN", "Transit"]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Web"
+            networks = ["DMZ", "Transit"]
+            base_box = "Debian"
+
+            [[systems]]
+            name = "DMZ Router"
+            networks = ["DMZ"]
+            base_box = "VyOS"
+            router = true
+            {}
+            "#,
+            NETWORKS
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            LabBuilderError::NoRoute {
+                location: Location::new("systems[0]"),
+                system: "Router".to_string(),
+                network: "DMZ".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn routing_through_two_equal_routers_should_fail_with_msg() {
+        let error = resolve(&format!(
+            r#"
+            [scenario]
+            name = "Test scenario"
+
+            [[systems]]
+            name = "Desktop"
+            networks = ["LAN"]
+            base_box = "Debian"
+
+            [[systems]]
+            name = "Router A"
+            networks = ["LAN", "Transit"]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Router B"
+            networks = ["LAN", "Transit"]
+            base_box = "VyOS"
+            router = true
+
+            [[systems]]
+            name = "Server"
+            networks = ["Transit", "DMZ"]
+            base_box = "Debian"
+            {}
+            "#,
+            NETWORKS
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            error,
+            LabBuilderError::AmbiguousRoute {
+                location: Location::new("systems[0]"),
+                system: "Desktop".to_string(),
+                network: "Transit".to_string(),
+                routers: vec!["Router A".to_string(), "Router B".to_string()],
+            }
+        );
+    }
+}
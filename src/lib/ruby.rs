@@ -0,0 +1,128 @@
+use crate::unique_names;
+
+use unicode_casefold::UnicodeCaseFold;
+
+/// Words that can't be used as local variable names in Ruby, plus `config`, which would shadow
+/// the block variable of `Vagrant.configure`.
+const RESERVED_WORDS: &[&str] = &[
+    "__encoding__",
+    "__file__",
+    "__line__",
+    "alias",
+    "and",
+    "begin",
+    "break",
+    "case",
+    "class",
+    "config",
+    "def",
+    "defined",
+    "do",
+    "else",
+    "elsif",
+    "end",
+    "ensure",
+    "false",
+    "for",
+    "if",
+    "in",
+    "module",
+    "next",
+    "nil",
+    "not",
+    "or",
+    "redo",
+    "rescue",
+    "retry",
+    "return",
+    "self",
+    "super",
+    "then",
+    "true",
+    "undef",
+    "unless",
+    "until",
+    "when",
+    "while",
+    "yield",
+];
+
+/// Quotes a value as a double-quoted Ruby string literal. Backslashes, quotes and `#` are
+/// escaped, so values can't end the literal early or interpolate code with `#{}`.
+pub fn string_literal(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => literal.push_str(r"\\"),
+            '"' => literal.push_str(r#"\""#),
+            '#' => literal.push_str(r"\#"),
+            '\n' => literal.push_str(r"\n"),
+            '\r' => literal.push_str(r"\r"),
+            '\t' => literal.push_str(r"\t"),
+            c if c.is_control() => literal.push_str(&format!(r"\u{{{:x}}}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Turns a name into a valid Ruby local variable name by case folding it and replacing
+/// anything other than ASCII letters, digits and underscores with an underscore.
+pub fn identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .case_fold()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    if RESERVED_WORDS.contains(&identifier.as_str()) {
+        identifier.push('_');
+    }
+
+    identifier
+}
+
+/// Builds an identifier for each name, in order. Names that sanitise to the same identifier,
+/// such as "Web-1" and "web_1", are told apart by a numeric suffix on every name after the first.
+pub fn unique_identifiers<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Vec<String> {
+    unique_names::unique_names(names, identifier, "_", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_should_replace_invalid_characters() {
+        assert_eq!(identifier("Test System"), "test_system");
+        assert_eq!(identifier("web-01"), "web_01");
+        assert_eq!(identifier("01-dc"), "_01_dc");
+        assert_eq!(identifier("End"), "end_");
+        assert_eq!(identifier("config"), "config_");
+    }
+
+    #[test]
+    fn unique_identifiers_should_disambiguate_collisions() {
+        assert_eq!(
+            unique_identifiers(vec!["Web-1", "web_1", "WEB 1", "web_1_2"]),
+            vec!["web_1", "web_1_2", "web_1_3", "web_1_2_2"]
+        );
+    }
+
+    #[test]
+    fn string_literal_should_escape_quotes_and_interpolation() {
+        assert_eq!(string_literal("Desktop"), r#""Desktop""#);
+        assert_eq!(
+            string_literal(r#"Evil" #{`rm -rf /`} \"#),
+            r#""Evil\" \#{`rm -rf /`} \\""#
+        );
+        assert_eq!(string_literal("a\nb"), r#""a\nb""#);
+    }
+}
@@ -1,185 +1,718 @@
-use crate::lib::indentation_aware_string_builder::{
-    IndentationAwareStringBuilder, IndentationType,
-};
-use crate::lib::network::{Network, NetworkType};
-use crate::lib::system::System;
-
-use ipnet::Ipv4Net;
+use crate::diagnostic::{self, Diagnostic};
+use crate::error::{LabBuilderError, Location};
+use crate::firewall::{Firewall, FirewallAction, FirewallRule, FirewallTool};
+use crate::forwarded_port::ForwardedPort;
+use crate::mac_address::MacAddress;
+use crate::name_resolution::{self, NameResolution};
+use crate::network::{AllocationMode, Network, NetworkType};
+use crate::provider::Provider;
+use crate::resolved_scenario::{ResolvedScenario, ResolvedSystem};
+use crate::routing;
+use crate::schema;
+use crate::system::System;
+
+use serde::Deserialize;
 use toml::Value;
-use unicode_casefold::UnicodeCaseFold;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
-#[allow(unused_imports)]
-use std::str::FromStr;
 
 #[derive(Debug, PartialEq)]
 pub struct Scenario {
     pub name: String,
+    pub allocation: AllocationMode,
+    pub provider: Provider,
+    pub management_network: Option<String>,
+    pub domain: String,
+    pub name_resolution: Option<NameResolution>,
+    pub firewall: Firewall,
     pub systems: Vec<System>,
     pub networks: Vec<Rc<Network>>,
 }
 
+const SCENARIO_KEYS: &[&str] = &["scenario", "networks", "systems", "firewall_rules"];
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a scenario table")]
+pub struct ScenarioHeader {
+    pub name: String,
+    #[serde(default)]
+    pub allocation: AllocationMode,
+    #[serde(default)]
+    pub provider: Provider,
+    pub management_network: Option<String>,
+    pub domain: Option<String>,
+    pub name_resolution: Option<NameResolution>,
+    #[serde(default)]
+    pub firewall_policy: FirewallAction,
+    #[serde(default)]
+    pub firewall_tool: FirewallTool,
+}
+
+/// Positions of the successfully parsed networks, systems and firewall rules in the scenario
+/// source, so checks run after parsing can point at the right table even when earlier entries
+/// failed to parse.
+struct SourceIndices {
+    networks: Vec<usize>,
+    systems: Vec<usize>,
+    firewall_rules: Vec<usize>,
+}
+
 impl Scenario {
-    pub fn from_toml(
+    pub fn from_toml(scenario_toml: &Value) -> Result<Scenario, LabBuilderError> {
+        let mut diagnostics = Vec::new();
+        let (scenario, _) = Scenario::parse(scenario_toml, &mut diagnostics);
+
+        match diagnostics.into_iter().find(Diagnostic::is_error) {
+            Some(diagnostic) => Err(diagnostic.error),
+            None => Ok(scenario),
+        }
+    }
+
+    /// Parses the scenario and checks it for every problem that can be found before networking
+    /// is configured, rather than stopping at the first. On success the scenario is returned
+    /// along with any warnings; otherwise all errors and warnings found are returned.
+    pub fn validate(scenario_toml: &Value) -> Result<(Scenario, Vec<Diagnostic>), Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        let (scenario, indices) = Scenario::parse(scenario_toml, &mut diagnostics);
+
+        let declared_network_names: HashSet<&str> = scenario_toml
+            .get("networks")
+            .and_then(Value::as_array)
+            .map(|networks| {
+                networks
+                    .iter()
+                    .filter_map(|network| network.get("name").and_then(Value::as_str))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let declared_system_names: HashSet<&str> = scenario_toml
+            .get("systems")
+            .and_then(Value::as_array)
+            .map(|systems| {
+                systems
+                    .iter()
+                    .filter_map(|system| system.get("name").and_then(Value::as_str))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        scenario.check_network_references(&declared_network_names, &indices, &mut diagnostics);
+        scenario.check_management_network(&declared_network_names, &mut diagnostics);
+        scenario.check_subnet_capacity(&indices, &mut diagnostics);
+        scenario.check_forwarded_ports(&indices, &mut diagnostics);
+        scenario.check_static_ips(&indices, &mut diagnostics);
+        for error in scenario.duplicate_mac_addresses(&indices.systems) {
+            diagnostics.push(Diagnostic::error(error));
+        }
+        scenario.check_unused_networks(&indices, &mut diagnostics);
+        for (rule, index) in scenario
+            .firewall
+            .rules
+            .iter()
+            .zip(indices.firewall_rules.iter())
+        {
+            let checked_rule = scenario
+                .check_firewall_rule(rule, &declared_network_names, &declared_system_names)
+                .map_err(|e| e.within(&format!("firewall_rules[{}]", index)));
+            diagnostic::collect(checked_rule, &mut diagnostics);
+        }
+
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            Err(diagnostics)
+        } else {
+            Ok((scenario, diagnostics))
+        }
+    }
+
+    fn parse(
         scenario_toml: &Value,
-    ) -> Result<Scenario, std::boxed::Box<std::error::Error>> {
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> (Scenario, SourceIndices) {
         let mut scenario = Scenario {
             name: "".into(),
+            allocation: AllocationMode::Sequential,
+            provider: Provider::VirtualBox,
+            management_network: None,
+            domain: name_resolution::DEFAULT_DOMAIN.to_string(),
+            name_resolution: None,
+            firewall: Firewall::default(),
             networks: Vec::new(),
             systems: Vec::new(),
         };
+        let mut indices = SourceIndices {
+            networks: Vec::new(),
+            systems: Vec::new(),
+            firewall_rules: Vec::new(),
+        };
 
-        scenario.name = scenario_toml
+        let header = scenario_toml
             .get("scenario")
-            .ok_or("Could not get scenario from configuration")?
-            .get("name")
-            .ok_or("Could not read name of scenario from configuration")?
-            .as_str()
-            .ok_or("Could not read name of scenario as a string")?
-            .into();
-
-        let networks: Result<Vec<Rc<Network>>, std::boxed::Box<std::error::Error>> = scenario_toml
+            .ok_or_else(|| LabBuilderError::missing_field("scenario"))
+            .and_then(|header| {
+                schema::from_value::<ScenarioHeader>(header).map_err(|e| e.within("scenario"))
+            });
+        if let Some(header) = diagnostic::collect(header, diagnostics) {
+            scenario.name = header.name;
+            scenario.allocation = header.allocation;
+            scenario.provider = header.provider;
+            scenario.management_network = header.management_network;
+            scenario.name_resolution = header.name_resolution;
+            scenario.firewall.policy = header.firewall_policy;
+            scenario.firewall.tool = header.firewall_tool;
+            if let Some(domain) = header.domain {
+                let checked_domain =
+                    name_resolution::check_domain(&domain).map_err(|e| e.within("scenario"));
+                if diagnostic::collect(checked_domain, diagnostics).is_some() {
+                    scenario.domain = domain;
+                }
+            }
+        }
+
+        let networks = scenario_toml
             .get("networks")
-            .ok_or("Could not read networks from configuration")?
-            .as_array()
-            .ok_or("Could not read networks from configuration")?
+            .ok_or_else(|| LabBuilderError::missing_field("networks"))
+            .and_then(|networks| {
+                networks
+                    .as_array()
+                    .ok_or_else(|| LabBuilderError::wrong_type("networks", "an array of tables"))
+            });
+        for (index, network_toml) in diagnostic::collect(networks, diagnostics)
             .into_iter()
-            .map(Network::from_toml)
-            .collect();
-
-        scenario.networks.append(&mut networks?);
+            .flatten()
+            .enumerate()
+        {
+            let network = Network::from_toml(network_toml)
+                .map_err(|e| e.within(&format!("networks[{}]", index)));
+            if let Some(network) = diagnostic::collect(network, diagnostics) {
+                scenario.networks.push(network);
+                indices.networks.push(index);
+            }
+        }
 
-        scenario.are_network_names_unique()?;
+        scenario.check_network_names_unique(&indices, diagnostics);
 
-        let systems: Result<Vec<System>, std::boxed::Box<std::error::Error>> = scenario_toml
+        let systems = scenario_toml
             .get("systems")
-            .ok_or("Could not get systems from configuration")?
-            .as_array()
-            .ok_or("Could not get systems from configuration")?
+            .ok_or_else(|| LabBuilderError::missing_field("systems"))
+            .and_then(|systems| {
+                systems
+                    .as_array()
+                    .ok_or_else(|| LabBuilderError::wrong_type("systems", "an array of tables"))
+            });
+        for (index, system_toml) in diagnostic::collect(systems, diagnostics)
             .into_iter()
-            .map(|system_toml| System::from_toml(&system_toml))
-            .collect();
+            .flatten()
+            .enumerate()
+        {
+            let system = System::from_toml(system_toml)
+                .map_err(|e| e.within(&format!("systems[{}]", index)));
+            if let Some(system) = diagnostic::collect(system, diagnostics) {
+                scenario.systems.push(system);
+                indices.systems.push(index);
+            }
+        }
 
-        scenario.systems.append(&mut systems?);
+        scenario.check_system_names_unique(&indices, diagnostics);
+
+        let no_rules = Vec::new();
+        let firewall_rules = match scenario_toml.get("firewall_rules") {
+            Some(rules) => rules
+                .as_array()
+                .ok_or_else(|| LabBuilderError::wrong_type("firewall_rules", "an array of tables")),
+            None => Ok(&no_rules),
+        };
+        for (index, rule_toml) in diagnostic::collect(firewall_rules, diagnostics)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let rule = schema::from_value(rule_toml)
+                .and_then(FirewallRule::from_definition)
+                .map_err(|e| e.within(&format!("firewall_rules[{}]", index)));
+            if let Some(rule) = diagnostic::collect(rule, diagnostics) {
+                scenario.firewall.rules.push(rule);
+                indices.firewall_rules.push(index);
+            }
+        }
 
-        scenario.are_system_names_unique()?;
+        for error in schema::unknown_keys(scenario_toml, SCENARIO_KEYS) {
+            diagnostics.push(Diagnostic::error(error));
+        }
 
-        Ok(scenario)
+        (scenario, indices)
     }
 
-    fn are_network_names_unique(&self) -> Result<(), String> {
-        let names = self.networks.iter().fold(HashMap::new(), |mut acc, x| {
-            acc.entry((x.name).to_string())
-                .and_modify(|e| *e += 1)
-                .or_insert(1);
+    fn check_network_names_unique(
+        &self,
+        indices: &SourceIndices,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let names: Vec<&str> = self.networks.iter().map(|net| net.name.as_str()).collect();
+
+        for index in duplicates(&names) {
+            diagnostics.push(Diagnostic::error(LabBuilderError::DuplicateName {
+                location: Location::new(&format!("networks[{}].name", indices.networks[index])),
+                kind: "network",
+                name: names[index].to_string(),
+            }));
+        }
+    }
 
-            acc
-        });
+    fn check_system_names_unique(
+        &self,
+        indices: &SourceIndices,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let names: Vec<&str> = self.systems.iter().map(|sys| sys.name.as_str()).collect();
+
+        for index in duplicates(&names) {
+            diagnostics.push(Diagnostic::error(LabBuilderError::DuplicateName {
+                location: Location::new(&format!("systems[{}].name", indices.systems[index])),
+                kind: "system",
+                name: names[index].to_string(),
+            }));
+        }
+    }
 
-        let dup_names: Vec<&String> = names
-            .iter()
-            .filter_map(|n| match n.1 {
-                1 => None,
-                _ => Some(n.0),
-            })
-            .collect();
+    fn check_network_references(
+        &self,
+        declared_network_names: &HashSet<&str>,
+        indices: &SourceIndices,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        for (system, system_index) in self.systems.iter().zip(indices.systems.iter()) {
+            for (index, network_name) in system.network_names().iter().enumerate() {
+                if !declared_network_names.contains(network_name.as_str()) {
+                    diagnostics.push(Diagnostic::error(
+                        LabBuilderError::UnknownNetworkReference {
+                            location: Location::new(&format!(
+                                "systems[{}].networks[{}]",
+                                system_index, index
+                            )),
+                            system: system.name.to_string(),
+                            network: network_name.to_string(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
 
-        let first_dupe_name = dup_names.iter().nth(0);
+    fn check_management_network(
+        &self,
+        declared_network_names: &HashSet<&str>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let management_network = match &self.management_network {
+            Some(management_network) => management_network,
+            None => return,
+        };
 
-        match first_dupe_name {
-            Some(name) => Err(format!(
-                r#"Multiple networks parsed with name "{}". Network names must be unique."#,
-                name
-            )),
-            None => Ok(()),
+        if !declared_network_names.contains(management_network.as_str()) {
+            diagnostics.push(Diagnostic::error(LabBuilderError::invalid_value(
+                "scenario.management_network",
+                &format!(r#"Network "{}" is not defined."#, management_network),
+            )));
+        } else if self
+            .networks
+            .iter()
+            .any(|net| &net.name == management_network && net.network_type == NetworkType::Public)
+        {
+            diagnostics.push(Diagnostic::error(LabBuilderError::invalid_value(
+                "scenario.management_network",
+                "Systems are not given addresses on public networks, so the management network must be internal.",
+            )));
         }
     }
 
-    fn are_system_names_unique(&self) -> Result<(), String> {
-        let names = self.systems.iter().fold(HashMap::new(), |mut acc, x| {
-            acc.entry((x.name).to_string())
-                .and_modify(|e| *e += 1)
-                .or_insert(1);
+    fn check_subnet_capacity(&self, indices: &SourceIndices, diagnostics: &mut Vec<Diagnostic>) {
+        for (net, index) in self.networks.iter().zip(indices.networks.iter()) {
+            let requested_hosts = self
+                .systems
+                .iter()
+                .flat_map(|system| system.network_names().iter())
+                .filter(|&network_name| network_name == &net.name)
+                .count();
+
+            let exhausted_subnet = if net
+                .free_host_count()
+                .is_some_and(|free_hosts| requested_hosts as u64 > free_hosts)
+            {
+                Some("subnet")
+            } else if net
+                .free_ipv6_host_count()
+                .is_some_and(|free_hosts| requested_hosts as u128 > free_hosts)
+            {
+                Some("ipv6_subnet")
+            } else {
+                None
+            };
+
+            if let Some(field) = exhausted_subnet {
+                diagnostics.push(Diagnostic::error(LabBuilderError::SubnetExhausted {
+                    location: Location::new(&format!("networks[{}].{}", index, field)),
+                    network: net.name.to_string(),
+                }));
+            }
+        }
+    }
 
-            acc
-        });
+    /// Reports every forwarded port needing a host port an earlier one already uses.
+    fn check_forwarded_ports(&self, indices: &SourceIndices, diagnostics: &mut Vec<Diagnostic>) {
+        let mut forwarded: Vec<(&str, &ForwardedPort)> = Vec::new();
+
+        for (system, system_index) in self.systems.iter().zip(indices.systems.iter()) {
+            for (index, port) in system.forwarded_ports.iter().enumerate() {
+                let collision = forwarded
+                    .iter()
+                    .find(|(_, other_port)| port.collides_with(other_port));
+
+                match collision {
+                    Some((other_system, _)) => {
+                        diagnostics.push(Diagnostic::error(LabBuilderError::HostPortInUse {
+                            location: Location::new(&format!(
+                                "systems[{}].forwarded_ports[{}].host",
+                                system_index, index
+                            )),
+                            port: port.host,
+                            protocol: port.protocol.to_string(),
+                            system: other_system.to_string(),
+                        }))
+                    }
+                    None => forwarded.push((&system.name, port)),
+                }
+            }
+        }
+    }
 
-        let dup_names: Vec<&String> = names
+    /// Reports every NIC given a static IP on a public network that an earlier NIC already has.
+    /// Addresses on other networks are checked when they're reserved.
+    fn check_static_ips(&self, indices: &SourceIndices, diagnostics: &mut Vec<Diagnostic>) {
+        let public_networks: HashSet<&str> = self
+            .networks
             .iter()
-            .filter_map(|n| match n.1 {
-                1 => None,
-                _ => Some(n.0),
-            })
+            .filter(|net| net.network_type == NetworkType::Public)
+            .map(|net| net.name.as_str())
             .collect();
+        let mut assigned: HashSet<(&str, Ipv4Addr)> = HashSet::new();
+
+        for (system, system_index) in self.systems.iter().zip(indices.systems.iter()) {
+            let static_ips = system
+                .network_names()
+                .iter()
+                .zip(system.reservations().iter())
+                .enumerate()
+                .filter(|(_, (network_name, _))| public_networks.contains(network_name.as_str()));
+            for (index, (network_name, reservation)) in static_ips {
+                let ip = match reservation.ipv4 {
+                    Some(ip) => ip,
+                    None => continue,
+                };
+                if !assigned.insert((network_name, ip)) {
+                    diagnostics.push(Diagnostic::error(LabBuilderError::invalid_value(
+                        &format!("systems[{}].networks[{}].ip", system_index, index),
+                        &format!(
+                            r#"Address {} is already given to another NIC on network "{}"."#,
+                            ip, network_name
+                        ),
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Finds every NIC whose MAC address, generated or given, an earlier NIC already has.
+    /// `system_indices` maps each system to its position in the scenario file.
+    fn duplicate_mac_addresses(&self, system_indices: &[usize]) -> Vec<LabBuilderError> {
+        let mut assigned: HashMap<MacAddress, &str> = HashMap::new();
+        let mut duplicates = Vec::new();
+
+        for (system, system_index) in self.systems.iter().zip(system_indices.iter()) {
+            for (index, mac) in system.mac_addresses(&self.name).into_iter().enumerate() {
+                match assigned.get(&mac) {
+                    Some(other_system) => duplicates.push(LabBuilderError::DuplicateMacAddress {
+                        location: Location::new(&format!(
+                            "systems[{}].networks[{}]",
+                            system_index, index
+                        )),
+                        mac: mac.to_string(),
+                        system: other_system.to_string(),
+                    }),
+                    None => {
+                        assigned.insert(mac, &system.name);
+                    }
+                }
+            }
+        }
 
-        let first_dupe_name = dup_names.iter().nth(0);
+        duplicates
+    }
 
-        match first_dupe_name {
-            Some(name) => Err(format!(
-                r#"Multiple systems parsed with name "{}". System names must be unique."#,
-                name
-            )),
-            None => Ok(()),
+    fn check_unused_networks(&self, indices: &SourceIndices, diagnostics: &mut Vec<Diagnostic>) {
+        for (net, index) in self.networks.iter().zip(indices.networks.iter()) {
+            let used = self
+                .systems
+                .iter()
+                .any(|system| system.network_names().contains(&net.name));
+
+            if !used {
+                diagnostics.push(Diagnostic::warning(LabBuilderError::UnusedNetwork {
+                    location: Location::new(&format!("networks[{}]", index)),
+                    network: net.name.to_string(),
+                }));
+            }
         }
     }
 
-    pub fn to_vagrantfile(&self) -> Result<String, std::boxed::Box<std::error::Error>> {
-        let mut builder = IndentationAwareStringBuilder::new();
-        builder
-            .with_indentation_type(IndentationType::Spaces)
-            .with_tab_size(4);
-
-        builder.add("Vagrant.configure(\"2\") do |config|".to_string());
-        builder.increase_indentation();
-
-        for system in self.systems.iter() {
-            let system_name_lower = system.name.case_fold().collect::<String>();
-            builder.add(format!(
-                r#"config.vm.define "{}" do |{}|"#,
-                system.name, system_name_lower
-            ));
-            builder.increase_indentation();
-
-            builder.add(format!(
-                r#"{}.vm.box = "{}""#,
-                system_name_lower, system.base_box
-            ));
-
-            for net in system.networks.iter().cloned() {
-                match net.network_type {
-                    NetworkType::Internal => {
-                        for lease in system.leased_network_addresses[&net.name].iter() {
-                            builder.add(format!(
-                                r#"{}.vm.network "private_network", ip: "{}", virtualbox__intnet: "{}""#,
-                                system_name_lower, lease, net.name
-                            ));
-                        }
-                    }
-                    NetworkType::Public => {
-                        builder.add(format!(r#"{}.vm.network "public_network""#, system.name))
-                    }
+    /// Checks that the source and destination of a firewall rule name a system, or a network
+    /// systems have addresses on.
+    fn check_firewall_rule(
+        &self,
+        rule: &FirewallRule,
+        declared_network_names: &HashSet<&str>,
+        declared_system_names: &HashSet<&str>,
+    ) -> Result<(), LabBuilderError> {
+        for (field, name) in rule.endpoints() {
+            if declared_network_names.contains(name) {
+                let public = self
+                    .networks
+                    .iter()
+                    .any(|net| net.name == name && net.network_type == NetworkType::Public);
+                if public {
+                    return Err(LabBuilderError::invalid_value(
+                        field,
+                        &format!(
+                            r#"Network "{}" is public, so there are no addresses to filter on."#,
+                            name
+                        ),
+                    ));
                 }
+            } else if !declared_system_names.contains(name) {
+                return Err(LabBuilderError::invalid_value(
+                    field,
+                    &format!(r#"No network or system is named "{}"."#, name),
+                ));
             }
+        }
+        Ok(())
+    }
 
-            builder.decrease_indentation();
-            builder.add("end".to_string());
+    /// Checks that every local file provisioners and synced folders refer to exists, relative to
+    /// the directory of the scenario file, and rewrites relative paths to be relative to the
+    /// directory generated files are written to, so the lab can be moved along with its files.
+    /// Both directories must be absolute, or relative to the same directory.
+    pub fn resolve_local_files(
+        &mut self,
+        scenario_dir: &Path,
+        output_dir: &Path,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for (system_index, system) in self.systems.iter_mut().enumerate() {
+            let provisioner_files =
+                system
+                    .provisioners
+                    .iter_mut()
+                    .enumerate()
+                    .filter_map(|(index, provisioner)| {
+                        provisioner.local_file_mut().map(|(field, path)| {
+                            (format!("provisioners[{}].{}", index, field), path)
+                        })
+                    });
+            let synced_folders =
+                system
+                    .synced_folders
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(index, folder)| {
+                        (
+                            format!("synced_folders[{}].host_path", index),
+                            &mut folder.host_path,
+                        )
+                    });
+
+            for (field, path) in provisioner_files.chain(synced_folders) {
+                let local_path = scenario_dir.join(&path);
+                if !local_path.exists() {
+                    diagnostics.push(Diagnostic::error(LabBuilderError::invalid_value(
+                        &format!("systems[{}].{}", system_index, field),
+                        &format!(r#"Path "{}" does not exist."#, path.display()),
+                    )));
+                } else if path.is_relative() {
+                    *path = relative_path(&local_path, output_dir);
+                }
+            }
         }
 
-        builder.decrease_indentation();
-        builder.add("end".to_string());
+        diagnostics
+    }
+
+    /// Warns about public networks bridged to interfaces that don't exist on this host, given the
+    /// provider the lab is built for and the directory listing the host's interfaces, such as
+    /// `/sys/class/net` on Linux. VirtualBox uses the first interface it finds, so only networks
+    /// with none of their interfaces present are reported. libvirt only uses the first interface.
+    pub fn check_bridge_interfaces(
+        &self,
+        provider: Provider,
+        interfaces_dir: &Path,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for (index, net) in self.networks.iter().enumerate() {
+            let candidates = match provider {
+                Provider::VirtualBox => &net.bridge[..],
+                Provider::Libvirt => &net.bridge[..net.bridge.len().min(1)],
+            };
+            let missing = !candidates.is_empty()
+                && candidates
+                    .iter()
+                    .all(|interface| !interfaces_dir.join(interface).exists());
+            if missing {
+                diagnostics.push(Diagnostic::warning(
+                    LabBuilderError::MissingBridgeInterface {
+                        location: Location::new(&format!("networks[{}].bridge", index)),
+                        interfaces: candidates.to_vec(),
+                    },
+                ));
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Leases addresses for every system, turning the scenario into one generators can use.
+    /// Static reservations are claimed for all systems first, so dynamic leases are handed out
+    /// around them regardless of system order. In `Hashed` mode systems are configured in name
+    /// order, so NICs probing past a collision between hashed leases end up with the same
+    /// addresses however the scenario is ordered. Routes through router systems are worked out once every system has its leases.
+    pub fn resolve(self) -> Result<ResolvedScenario, LabBuilderError> {
+        let network_names: HashSet<&str> = self.networks.iter().map(|n| n.name.as_str()).collect();
+        let system_names: HashSet<&str> = self.systems.iter().map(|s| s.name.as_str()).collect();
+        for (index, rule) in self.firewall.rules.iter().enumerate() {
+            self.check_firewall_rule(rule, &network_names, &system_names)
+                .map_err(|e| e.within(&format!("firewall_rules[{}]", index)))?;
+        }
+
+        let system_indices: Vec<usize> = (0..self.systems.len()).collect();
+        if let Some(error) = self
+            .duplicate_mac_addresses(&system_indices)
+            .into_iter()
+            .next()
+        {
+            return Err(error);
+        }
+
+        for (index, system) in self.systems.iter().enumerate() {
+            system
+                .reserve_addresses(&self.networks)
+                .map_err(|e| e.within(&format!("systems[{}]", index)))?;
+        }
+
+        let mut order: Vec<usize> = (0..self.systems.len()).collect();
+        if self.allocation == AllocationMode::Hashed {
+            order.sort_by(|&a, &b| self.systems[a].name.cmp(&self.systems[b].name));
+        }
+
+        let mut resolved_systems = Vec::new();
+        for index in order {
+            let resolved_system = self.systems[index]
+                .resolve(&self.name, &self.networks, self.allocation)
+                .map_err(|e| e.within(&format!("systems[{}]", index)))?;
+            resolved_systems.push((index, resolved_system));
+        }
+        resolved_systems.sort_by_key(|&(index, _)| index);
+
+        let mut systems: Vec<ResolvedSystem> = resolved_systems
+            .into_iter()
+            .map(|(_, system)| system)
+            .collect();
+        let routes = routing::compute_routes(&self.networks, &systems)?;
+        for (system, routes) in systems.iter_mut().zip(routes) {
+            system.routes = routes;
+        }
+
+        Ok(ResolvedScenario {
+            name: self.name,
+            provider: self.provider,
+            management_network: self.management_network,
+            domain: self.domain,
+            name_resolution: self.name_resolution,
+            firewall: self.firewall,
+            networks: self.networks,
+            systems,
+        })
+    }
+}
+
+/// Returns the indices of every name that repeats an earlier one.
+fn duplicates(names: &[&str]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !seen.insert(*name))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Removes `.` components and the components `..` climbs out of, without touching the file
+/// system, so symbolic links in the path are kept.
+fn normalise(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalised.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalised.pop();
+            }
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
 
-        Ok(builder.build_string())
+/// The path that leads from `base` to `path`. When `base` climbs out of the directory both are
+/// relative to, there's no way to tell which directory to climb back into, so `path` is returned.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = normalise(path);
+    let base = normalise(base);
+
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for component in base.components().skip(common) {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            _ => return path,
+        }
     }
+    relative.extend(path.components().skip(common));
+    relative
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::provisioner::Provisioner;
+
+    use ipnet::Ipv4Net;
+    use std::str::FromStr;
+
     #[test]
     fn parsing_toml_without_scenario_block_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [Notscenario]
             [[systems]]
@@ -194,15 +727,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            "Could not get scenario from configuration".to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("scenario")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_toml_without_scenario_name_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             [[systems]]
@@ -217,15 +750,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            "Could not read name of scenario from configuration".to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("scenario.name")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_toml_with_scenario_name_that_is_not_a_string_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = 42
@@ -241,15 +774,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            "Could not read name of scenario as a string".to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("scenario.name", "a string")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_toml_without_systems_array_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -261,15 +794,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            "Could not get systems from configuration".to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("systems")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_toml_without_networks_array_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -281,15 +814,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            "Could not read networks from configuration".to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("networks")
         );
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_for_system_with_networks_array_containing_non_existant_network_name_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_scenario_with_system_networks_array_containing_non_existant_network_name_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -304,18 +837,55 @@ mod tests {
             "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&input)?;
+        let scenario = Scenario::from_toml(&input)?;
+
+        assert_eq!(
+            scenario.resolve().unwrap_err(),
+            LabBuilderError::UnknownNetworkReference {
+                location: Location::new("systems[0].networks[0]"),
+                system: "Test System".to_string(),
+                network: "OtherNet".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_scenario_with_invalid_network_should_report_path_of_network(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            [[networks]]
+            name = "OtherNet"
+            type = "Internal"
+            subnet = 42
+        "#;
+
+        let error = Scenario::from_toml(&input.parse::<Value>()?)
+            .unwrap_err()
+            .with_source(input);
 
+        assert_eq!(error.location().path, "networks[1].subnet");
+        assert_eq!(error.location().line_col, Some((14, 12)));
         assert_eq!(
-            *scenario.systems[0].configure_networking(&scenario.networks).unwrap_err().description(),
-            r#"System "Test System" is configured to use network "OtherNet" but no network with that name could be found"#.to_string()
+            error.to_string(),
+            r#"Field "networks[1].subnet" has the wrong type. Expected a string. (line 15, column 13)"#
         );
         Ok(())
     }
 
     #[test]
     fn parsing_scenario_with_duplicate_network_names_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -335,16 +905,19 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            r#"Multiple networks parsed with name "TestNet". Network names must be unique."#
-                .to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::DuplicateName {
+                location: Location::new("networks[1].name"),
+                kind: "network",
+                name: "TestNet".to_string(),
+            }
         );
         Ok(())
     }
 
     #[test]
     fn parsing_scenario_with_duplicate_system_names_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -364,16 +937,480 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *Scenario::from_toml(&input).unwrap_err().description(),
-            r#"Multiple systems parsed with name "Test System". System names must be unique."#
-                .to_string()
+            Scenario::from_toml(&input).unwrap_err(),
+            LabBuilderError::DuplicateName {
+                location: Location::new("systems[1].name"),
+                kind: "system",
+                name: "Test System".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_should_report_every_error(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet", "OtherNet"]
+            [[systems]]
+            name = "Other System"
+            networks = ["TestNet", "TestNet", "TestNet"]
+            base_box = "Debian"
+            [[networks]]
+            name = 42
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.1.0/30"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(LabBuilderError::wrong_type("networks[0].name", "a string")),
+                Diagnostic::error(LabBuilderError::missing_field("systems[0].base_box")),
+                Diagnostic::error(LabBuilderError::SubnetExhausted {
+                    location: Location::new("networks[1].subnet"),
+                    network: "TestNet".to_string(),
+                }),
+            ]
         );
         Ok(())
     }
 
+    #[test]
+    fn validating_scenario_should_report_unknown_networks_and_warn_about_unused_networks(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["OtherNet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(LabBuilderError::UnknownNetworkReference {
+                    location: Location::new("systems[0].networks[0]"),
+                    system: "Test System".to_string(),
+                    network: "OtherNet".to_string(),
+                }),
+                Diagnostic::warning(LabBuilderError::UnusedNetwork {
+                    location: Location::new("networks[0]"),
+                    network: "TestNet".to_string(),
+                }),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_public_management_network_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            management_network = "Internet"
+            [[systems]]
+            name = "Test System"
+            networks = ["Internet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "Internet"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(LabBuilderError::invalid_value(
+                "scenario.management_network",
+                "Systems are not given addresses on public networks, so the management network must be internal."
+            ))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_local_files_should_report_missing_files_and_write_paths_relative_to_output(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            provisioners = [
+                { type = "File", source = "scenario.json", destination = "/tmp/scenario.json" },
+                { type = "Shell", path = "missing.sh" },
+            ]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let mut scenario = Scenario::from_toml(&input)?;
+        let diagnostics =
+            scenario.resolve_local_files(Path::new("test_data"), Path::new("./out/lab"));
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(LabBuilderError::invalid_value(
+                "systems[0].provisioners[1].path",
+                r#"Path "missing.sh" does not exist."#
+            ))]
+        );
+        assert_eq!(
+            scenario.systems[0].provisioners[0],
+            Provisioner::File {
+                source: PathBuf::from("../../test_data/scenario.json"),
+                destination: "/tmp/scenario.json".to_string(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn relative_path_should_lead_from_base_to_path() {
+        assert_eq!(
+            relative_path(Path::new("/lab/scripts/a.sh"), Path::new("/lab/out/")),
+            PathBuf::from("../scripts/a.sh")
+        );
+        assert_eq!(
+            relative_path(Path::new("lab/./a.sh"), Path::new("lab/out/../")),
+            PathBuf::from("a.sh")
+        );
+        assert_eq!(
+            relative_path(Path::new("a.sh"), Path::new("../out")),
+            PathBuf::from("a.sh")
+        );
+    }
+
+    #[test]
+    fn validating_scenario_with_colliding_host_ports_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Web"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            forwarded_ports = [{ guest = 80, host = 8080, host_ip = "127.0.0.1" }]
+            [[systems]]
+            name = "App"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            forwarded_ports = [
+                { guest = 53, host = 8080, protocol = "Udp" },
+                { guest = 80, host = 8080 },
+            ]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(LabBuilderError::HostPortInUse {
+                location: Location::new("systems[1].forwarded_ports[1].host"),
+                port: 8080,
+                protocol: "tcp".to_string(),
+                system: "Web".to_string(),
+            })]
+        );
+        assert_eq!(
+            diagnostics[0].error.to_string(),
+            r#"Host port 8080/tcp is already forwarded to system "Web"."#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_unresolvable_firewall_rules_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Web"
+            networks = ["TestNet", "Internet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            [[networks]]
+            name = "Internet"
+            type = "Public"
+            [[firewall_rules]]
+            source = "Web"
+            destination = "TestNet"
+            action = "Accept"
+            [[firewall_rules]]
+            source = "DMZ"
+            action = "Drop"
+            [[firewall_rules]]
+            destination = "Internet"
+            action = "Reject"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(LabBuilderError::invalid_value(
+                    "firewall_rules[1].source",
+                    r#"No network or system is named "DMZ"."#
+                )),
+                Diagnostic::error(LabBuilderError::invalid_value(
+                    "firewall_rules[2].destination",
+                    r#"Network "Internet" is public, so there are no addresses to filter on."#
+                )),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn checking_bridge_interfaces_should_warn_when_none_the_provider_uses_exist(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Attacker"
+            networks = ["Office", "Lab"]
+            base_box = "Kali"
+            [[networks]]
+            name = "Office"
+            type = "Public"
+            bridge = ["eth0", "eth1"]
+            [[networks]]
+            name = "Lab"
+            type = "Public"
+            bridge = ["wlan0", "eth1"]
+        "#
+        .parse::<Value>()?;
+
+        // Unique to this test run, so concurrent runs can't add or remove each other's interfaces.
+        let interfaces_dir = std::env::temp_dir().join(format!(
+            "labbuilder-{}-checking-bridge-interfaces",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(interfaces_dir.join("eth1"))?;
+
+        let scenario = Scenario::from_toml(&input)?;
+        assert_eq!(
+            scenario.check_bridge_interfaces(Provider::VirtualBox, &interfaces_dir),
+            vec![]
+        );
+        assert_eq!(
+            scenario.check_bridge_interfaces(Provider::Libvirt, &interfaces_dir),
+            vec![
+                Diagnostic::warning(LabBuilderError::MissingBridgeInterface {
+                    location: Location::new("networks[0].bridge"),
+                    interfaces: vec!["eth0".to_string()],
+                }),
+                Diagnostic::warning(LabBuilderError::MissingBridgeInterface {
+                    location: Location::new("networks[1].bridge"),
+                    interfaces: vec!["wlan0".to_string()],
+                }),
+            ]
+        );
+
+        std::fs::remove_dir_all(interfaces_dir.join("eth1"))?;
+        let diagnostics = scenario.check_bridge_interfaces(Provider::VirtualBox, &interfaces_dir);
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning(LabBuilderError::MissingBridgeInterface {
+                    location: Location::new("networks[0].bridge"),
+                    interfaces: vec!["eth0".to_string(), "eth1".to_string()],
+                }),
+                Diagnostic::warning(LabBuilderError::MissingBridgeInterface {
+                    location: Location::new("networks[1].bridge"),
+                    interfaces: vec!["wlan0".to_string(), "eth1".to_string()],
+                }),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].error.to_string(),
+            r#"None of the interfaces "eth0", "eth1" exist on this host, so none can be bridged to."#
+        );
+
+        std::fs::remove_dir_all(&interfaces_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_duplicate_mac_addresses_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "DHCP server"
+            networks = [{ name = "TestNet", mac = "02:00:00:00:00:01" }]
+            base_box = "Debian"
+            [[systems]]
+            name = "Client"
+            networks = [{ name = "TestNet" }, { name = "TestNet", mac = "02-00-00-00-00-01" }]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic::error(LabBuilderError::DuplicateMacAddress {
+                location: Location::new("systems[1].networks[1]"),
+                mac: "02:00:00:00:00:01".to_string(),
+                system: "DHCP server".to_string(),
+            })]
+        );
+        assert_eq!(
+            diagnostics[0].error.to_string(),
+            r#"MAC address 02:00:00:00:00:01 is already used by a NIC of system "DHCP server"."#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_shared_static_ip_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Attacker"
+            networks = [{ name = "Office", ip = "192.0.2.10", netmask = "255.255.255.0" }]
+            base_box = "Kali"
+            [[systems]]
+            name = "Victim"
+            networks = [{ name = "Office", ip = "192.0.2.11" }, { name = "Office", ip = "192.0.2.10" }]
+            base_box = "Debian"
+            [[networks]]
+            name = "Office"
+            type = "Public"
+            bridge = "eth0"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Scenario::validate(&input).unwrap_err(),
+            vec![Diagnostic::error(LabBuilderError::invalid_value(
+                "systems[1].networks[1].ip",
+                r#"Address 192.0.2.10 is already given to another NIC on network "Office"."#
+            ))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_misspelt_section_should_suggest_correct_name(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            [[netwroks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let diagnostics = Scenario::validate(&input).unwrap_err();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error(LabBuilderError::missing_field("networks")),
+                Diagnostic::error(LabBuilderError::UnknownField {
+                    location: Location::new("netwroks"),
+                    suggestion: Some("networks".to_string()),
+                }),
+                Diagnostic::error(LabBuilderError::UnknownNetworkReference {
+                    location: Location::new("systems[0].networks[0]"),
+                    system: "Test System".to_string(),
+                    network: "TestNet".to_string(),
+                }),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validating_scenario_with_only_warnings_should_succeed(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            [[networks]]
+            name = "OtherNet"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        let (scenario, warnings) = Scenario::validate(&input).unwrap();
+
+        assert_eq!(scenario.systems.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(!warnings[0].is_error());
+        Ok(())
+    }
+
     #[test]
     fn parsing_single_system_and_network_scenario_works(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
@@ -419,49 +1456,115 @@ mod tests {
     }
 
     #[test]
-    fn vagrantfile_output_for_simple_scenario_works(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn validating_scenario_should_report_exhausted_ipv6_subnet(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             [scenario]
             name = "Test scenario"
+            [[systems]]
+            name = "Test System"
+            networks = ["TestNet", "TestNet", "TestNet", "TestNet"]
+            base_box = "Debian"
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/126"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Scenario::validate(&input).unwrap_err(),
+            vec![Diagnostic::error(LabBuilderError::SubnetExhausted {
+                location: Location::new("networks[0].ipv6_subnet"),
+                network: "TestNet".to_string(),
+            })]
+        );
+        Ok(())
+    }
 
+    #[test]
+    fn hashed_allocation_should_probe_past_collisions_in_system_name_order(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            allocation = "Hashed"
+            [[systems]]
+            name = "Server"
+            networks = ["LAN"]
+            base_box = "Debian"
+            [[networks]]
+            name = "LAN"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#;
+        let grown_input = input.replace(
+            "allocation = \"Hashed\"",
+            "allocation = \"Hashed\"\n[[systems]]\nname = \"Backup52\"\nnetworks = [\"LAN\"]\nbase_box = \"Debian\"",
+        );
+        let fixed_input = grown_input.replace(
+            "networks = [\"LAN\"]\nbase_box",
+            "networks = [{ name = \"LAN\", ip = \"192.168.0.10\" }]\nbase_box",
+        );
+
+        let scenario = Scenario::from_toml(&input.parse::<Value>()?)?.resolve()?;
+        let lease = scenario.systems[0].nics[0].lease.unwrap();
+        assert_eq!(lease.ipv4, Some("192.168.0.132".parse()?));
+
+        // Backup52 hashes to the same address as Server and comes first by name, so it takes the
+        // address and Server probes on to the next one.
+        let grown_scenario = Scenario::from_toml(&grown_input.parse::<Value>()?)?.resolve()?;
+        assert_eq!(grown_scenario.systems[0].nics[0].lease, Some(lease));
+        assert_eq!(
+            grown_scenario.systems[1].nics[0].lease.unwrap().ipv4,
+            Some("192.168.0.133".parse()?)
+        );
+
+        let fixed_scenario = Scenario::from_toml(&fixed_input.parse::<Value>()?)?.resolve()?;
+        assert_eq!(fixed_scenario.systems[1].nics[0].lease, Some(lease));
+        Ok(())
+    }
+
+    #[test]
+    fn hashed_allocation_should_keep_addresses_when_systems_are_added(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            [scenario]
+            name = "Test scenario"
+            allocation = "Hashed"
             [[systems]]
             name = "Desktop"
             networks = ["LAN"]
             base_box = "Windows 10"
-
             [[systems]]
             name = "Server"
             networks = ["LAN"]
             base_box = "Debian"
-
             [[networks]]
             name = "LAN"
             type = "Internal"
-            subnet = "192.168.0.1/24"
-        "#
-        .parse::<Value>()?;
+            subnet = "10.0.0.0/16"
+        "#;
+        let grown_input = input.replace(
+            "allocation = \"Hashed\"",
+            "allocation = \"Hashed\"\n[[systems]]\nname = \"Attacker\"\nnetworks = [\"LAN\"]\nbase_box = \"Kali\"",
+        );
 
-        let mut scenario = Scenario::from_toml(&input)?;
+        let scenario = Scenario::from_toml(&input.parse::<Value>()?)?.resolve()?;
+        let grown_scenario = Scenario::from_toml(&grown_input.parse::<Value>()?)?;
+        assert_eq!(grown_scenario.allocation, AllocationMode::Hashed);
+        let grown_scenario = grown_scenario.resolve()?;
 
-        for system in scenario.systems.iter_mut() {
-            system.configure_networking(&scenario.networks)?;
-        }
-
-        let expected = r#"Vagrant.configure("2") do |config|
-    config.vm.define "Desktop" do |desktop|
-        desktop.vm.box = "Windows 10"
-        desktop.vm.network "private_network", ip: "192.168.0.1", virtualbox__intnet: "LAN"
-    end
-    config.vm.define "Server" do |server|
-        server.vm.box = "Debian"
-        server.vm.network "private_network", ip: "192.168.0.2", virtualbox__intnet: "LAN"
-    end
-end"#
-            .to_string();
-
-        assert_eq!(scenario.to_vagrantfile().unwrap(), expected);
+        assert_eq!(grown_scenario.systems.len(), 3);
+        assert_eq!(
+            scenario.systems[0].nics[0].lease,
+            grown_scenario.systems[1].nics[0].lease
+        );
+        assert_eq!(
+            scenario.systems[1].nics[0].lease,
+            grown_scenario.systems[2].nics[0].lease
+        );
         Ok(())
     }
-
 }
@@ -0,0 +1,269 @@
+use crate::error::{closest_match, LabBuilderError, Location};
+
+use serde::de::{
+    self, Deserialize, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use toml::Value;
+
+use std::collections::btree_map;
+use std::iter::Enumerate;
+use std::slice;
+
+/// Deserialises a definition struct from a parsed scenario value. Unlike going through
+/// `Value::try_into`, errors come back as `LabBuilderError`s carrying the path of the field
+/// that caused them.
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de Value) -> Result<T, LabBuilderError> {
+    T::deserialize(ValueDeserializer { value })
+}
+
+/// Reports every key of a table that isn't one of `known_keys`, suggesting the closest known
+/// key for likely typos.
+pub fn unknown_keys(table: &Value, known_keys: &'static [&'static str]) -> Vec<LabBuilderError> {
+    match table.as_table() {
+        Some(table) => table
+            .keys()
+            .filter(|key| !known_keys.contains(&key.as_str()))
+            .map(|key| LabBuilderError::UnknownField {
+                location: Location::new(key),
+                suggestion: closest_match(key, known_keys).map(str::to_string),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+struct ValueDeserializer<'de> {
+    value: &'de Value,
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = LabBuilderError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, LabBuilderError> {
+        match self.value {
+            Value::String(value) => visitor.visit_borrowed_str(value),
+            Value::Integer(value) => visitor.visit_i64(*value),
+            Value::Float(value) => visitor.visit_f64(*value),
+            Value::Boolean(value) => visitor.visit_bool(*value),
+            Value::Datetime(value) => visitor.visit_string(value.to_string()),
+            Value::Array(values) => visitor.visit_seq(ArrayAccess {
+                values: values.iter().enumerate(),
+            }),
+            Value::Table(table) => visitor.visit_map(TableAccess {
+                entries: table.iter(),
+                key: "",
+                value: None,
+            }),
+        }
+    }
+
+    // Scenario formats have no null, so a value that is present is always `Some`.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, LabBuilderError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, LabBuilderError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LabBuilderError> {
+        match self.value {
+            Value::String(variant) => visitor.visit_enum(variant.as_str().into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ArrayAccess<'de> {
+    values: Enumerate<slice::Iter<'de, Value>>,
+}
+
+impl<'de> SeqAccess<'de> for ArrayAccess<'de> {
+    type Error = LabBuilderError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, LabBuilderError> {
+        match self.values.next() {
+            Some((index, value)) => seed
+                .deserialize(ValueDeserializer { value })
+                .map(Some)
+                .map_err(|e| e.within(&format!("[{}]", index))),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+struct TableAccess<'de> {
+    entries: btree_map::Iter<'de, String, Value>,
+    key: &'de str,
+    value: Option<&'de Value>,
+}
+
+impl<'de> MapAccess<'de> for TableAccess<'de> {
+    type Error = LabBuilderError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, LabBuilderError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.key = key;
+                self.value = Some(value);
+                seed.deserialize(key.as_str().into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, LabBuilderError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| <LabBuilderError as de::Error>::custom("value requested before key"))?;
+
+        seed.deserialize(ValueDeserializer { value })
+            .map_err(|e| e.within(self.key))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Definition {
+        name: String,
+        ports: Vec<u16>,
+        comment: Option<String>,
+    }
+
+    #[test]
+    fn from_value_should_deserialise_definitions(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test"
+            ports = [22, 80]
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            from_value::<Definition>(&input)?,
+            Definition {
+                name: "Test".to_string(),
+                ports: vec![22, 80],
+                comment: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_value_should_report_path_of_invalid_array_element(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test"
+            ports = [22, 70000]
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            from_value::<Definition>(&input).unwrap_err(),
+            LabBuilderError::invalid_value("ports[1]", "Expected u16, found integer `70000`.")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_value_should_report_path_of_wrongly_typed_field(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test"
+            ports = "http"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            from_value::<Definition>(&input).unwrap_err(),
+            LabBuilderError::wrong_type("ports", "a sequence")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_value_should_reject_unknown_fields_with_suggestion(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test"
+            ports = []
+            comemnt = "typo"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            from_value::<Definition>(&input).unwrap_err(),
+            LabBuilderError::UnknownField {
+                location: Location::new("comemnt"),
+                suggestion: Some("comment".to_string()),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_keys_should_report_every_unknown_key(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test"
+            nmae = "Test"
+            colour = "blue"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            unknown_keys(&input, &["name"]),
+            vec![
+                LabBuilderError::UnknownField {
+                    location: Location::new("colour"),
+                    suggestion: None,
+                },
+                LabBuilderError::UnknownField {
+                    location: Location::new("nmae"),
+                    suggestion: Some("name".to_string()),
+                },
+            ]
+        );
+        Ok(())
+    }
+}
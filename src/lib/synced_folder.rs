@@ -0,0 +1,57 @@
+use serde::Deserialize;
+
+use std::path::PathBuf;
+
+/// A folder on the host shared with a system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncedFolder {
+    pub host_path: PathBuf,
+    pub guest_path: String,
+    /// How the folder is shared. The provider's default is used when unset.
+    pub folder_type: Option<SyncedFolderType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(expecting = "the name of a synced folder type")]
+pub enum SyncedFolderType {
+    VirtualBox,
+    Nfs,
+    Rsync,
+    Smb,
+    #[serde(rename = "9p")]
+    NineP,
+    VirtioFs,
+}
+
+impl SyncedFolderType {
+    /// The name Vagrant and its plugins use for the type.
+    pub fn vagrant_name(self) -> &'static str {
+        match self {
+            SyncedFolderType::VirtualBox => "virtualbox",
+            SyncedFolderType::Nfs => "nfs",
+            SyncedFolderType::Rsync => "rsync",
+            SyncedFolderType::Smb => "smb",
+            SyncedFolderType::NineP => "9p",
+            SyncedFolderType::VirtioFs => "virtiofs",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a synced folder table")]
+pub struct SyncedFolderDefinition {
+    pub host_path: String,
+    pub guest_path: String,
+    #[serde(rename = "type")]
+    pub folder_type: Option<SyncedFolderType>,
+}
+
+impl SyncedFolder {
+    pub fn from_definition(definition: SyncedFolderDefinition) -> SyncedFolder {
+        SyncedFolder {
+            host_path: PathBuf::from(definition.host_path),
+            guest_path: definition.guest_path,
+            folder_type: definition.folder_type,
+        }
+    }
+}
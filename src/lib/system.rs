@@ -1,128 +1,410 @@
-use crate::lib::network::{Network, NetworkType};
+use crate::error::{LabBuilderError, Location};
+use crate::forwarded_port::{ForwardedPort, ForwardedPortDefinition};
+use crate::hardware::{DiskDefinition, Hardware, Size};
+use crate::mac_address::MacAddress;
+use crate::network::{AllocationMode, Lease, Network, NetworkType};
+use crate::provisioner::{Provisioner, ProvisionerDefinition};
+use crate::resolved_scenario::{ResolvedNic, ResolvedSystem};
+use crate::schema;
+use crate::synced_folder::{SyncedFolder, SyncedFolderDefinition};
 
-use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use ipnet::Ipv4Net;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::rc::Rc;
 use toml::Value;
 
 #[derive(Debug, PartialEq)]
 pub struct System {
     pub name: String,
-    pub networks: Vec<Rc<Network>>,
     network_names: Vec<String>,
+    reservations: Vec<Lease>,
+    /// Netmasks of the static IPs of NICs on public networks.
+    netmasks: Vec<Option<Ipv4Addr>>,
+    /// MAC addresses given in the NIC tables, which replace the generated ones.
+    mac_overrides: Vec<Option<MacAddress>>,
+    pub base_box: String,
+    pub tags: Vec<String>,
+    pub hardware: Hardware,
+    pub provisioners: Vec<Provisioner>,
+    pub forwarded_ports: Vec<ForwardedPort>,
+    pub synced_folders: Vec<SyncedFolder>,
+    /// Whether the system forwards traffic between its networks.
+    pub router: bool,
+    /// Whether the scenario's name resolution is set up on the system. The script doing so only
+    /// works on Linux, so systems opt in.
+    pub lab_names: bool,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a system table")]
+pub struct SystemDefinition {
+    pub name: String,
+    pub networks: Vec<NicDefinition>,
     pub base_box: String,
-    pub leased_network_addresses: HashMap<String, Vec<Ipv4Addr>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub cpus: Option<u32>,
+    pub memory: Option<Size>,
+    #[serde(default)]
+    pub disks: Vec<DiskDefinition>,
+    pub gui: Option<bool>,
+    #[serde(default)]
+    pub provisioners: Vec<ProvisionerDefinition>,
+    #[serde(default)]
+    pub forwarded_ports: Vec<ForwardedPortDefinition>,
+    #[serde(default)]
+    pub synced_folders: Vec<SyncedFolderDefinition>,
+    #[serde(default)]
+    pub router: bool,
+    #[serde(default)]
+    pub lab_names: bool,
+}
+
+/// A NIC is either just the name of the network it attaches to, or a table naming the network
+/// along with the addresses reserved for it. On a public network, `ip` and `netmask` are the
+/// static address of the NIC.
+#[derive(Debug, PartialEq)]
+pub struct NicDefinition {
+    pub name: String,
+    pub ip: Option<String>,
+    pub ipv6: Option<String>,
+    pub netmask: Option<String>,
+    pub mac: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for NicDefinition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NicDefinition, D::Error> {
+        deserializer.deserialize_any(NicDefinitionVisitor)
+    }
+}
+
+struct NicDefinitionVisitor;
+
+impl<'de> Visitor<'de> for NicDefinitionVisitor {
+    type Value = NicDefinition;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a network name or a NIC table")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<NicDefinition, E> {
+        Ok(NicDefinition {
+            name: name.to_string(),
+            ip: None,
+            ipv6: None,
+            netmask: None,
+            mac: None,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<NicDefinition, A::Error> {
+        NicTable::deserialize(de::value::MapAccessDeserializer::new(map)).map(|nic| NicDefinition {
+            name: nic.name,
+            ip: nic.ip,
+            ipv6: nic.ipv6,
+            netmask: nic.netmask,
+            mac: nic.mac,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, expecting = "a NIC table")]
+struct NicTable {
+    name: String,
+    ip: Option<String>,
+    ipv6: Option<String>,
+    netmask: Option<String>,
+    mac: Option<String>,
 }
 
 impl System {
-    pub fn from_toml(system_toml: &Value) -> Result<System, std::boxed::Box<std::error::Error>> {
-        let mut system = System {
-            name: "".into(),
-            networks: Vec::new(),
-            network_names: Vec::new(),
-            base_box: "".into(),
-            leased_network_addresses: HashMap::new(),
-        };
-
-        system.name = system_toml
-            .get("name")
-            .ok_or("Could not read name of system")?
-            .as_str()
-            .ok_or("Could not read name of system as a string")?
-            .into();
-
-        let network_names: Result<Vec<String>, std::boxed::Box<std::error::Error>> = system_toml
-            .get("networks")
-            .ok_or(format!(
-                "Could not read networks for system: {}",
-                system.name
-            ))?
-            .as_array()
-            .ok_or(format!(
-                "Could not read networks for system: {}",
-                system.name
-            ))?
-            .into_iter()
-            .map(|network_name_toml| {
-                let network_name = network_name_toml.as_str().ok_or(format!(
-                    "Could not parse networks for system: {}",
-                    system.name
-                ))?;
-                Ok(network_name.to_string())
-            })
-            .collect();
-
-        system.network_names.append(&mut network_names?);
-
-        system.base_box = system_toml
-            .get("base_box")
-            .ok_or(format!(
-                "Could not read base_box for system: {}",
-                system.name
-            ))?
-            .as_str()
-            .ok_or(format!(
-                "Could not read base_box as a string for system: {}",
-                system.name
-            ))?
-            .into();
-
-        Ok(system)
-    }
-
-    pub fn configure_networking(
-        &mut self,
-        scenario_networks: &Vec<Rc<Network>>,
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
-        let system_networks: Result<Vec<Rc<Network>>, std::boxed::Box<std::error::Error>> =
-            self.network_names.iter()
-                .map(|network_name|
-                    Ok(Rc::clone(scenario_networks
-                        .iter()
-                        .find(|&network| &network.name == network_name)
-                        .ok_or(format!(
-                            r#"System "{}" is configured to use network "{}" but no network with that name could be found"#,
-                            self.name, network_name
-                        ))?))
-                )
-                .collect();
-
-        self.networks.append(&mut system_networks?);
-
-        let internal_nets: Vec<Rc<Network>> = self
-            .networks
+    pub fn from_toml(system_toml: &Value) -> Result<System, LabBuilderError> {
+        System::from_definition(schema::from_value(system_toml)?)
+    }
+
+    pub fn from_definition(definition: SystemDefinition) -> Result<System, LabBuilderError> {
+        let mut network_names = Vec::new();
+        let mut reservations = Vec::new();
+        let mut netmasks = Vec::new();
+        let mut mac_overrides = Vec::new();
+
+        for (index, nic) in definition.networks.into_iter().enumerate() {
+            let reservation =
+                parse_reservation(&nic).map_err(|e| e.within(&format!("networks[{}]", index)))?;
+            let netmask = parse_netmask(&nic, reservation.ipv4)
+                .map_err(|e| e.within(&format!("networks[{}]", index)))?;
+            let mac_override = nic
+                .mac
+                .as_deref()
+                .map(parse_mac)
+                .transpose()
+                .map_err(|e| e.within(&format!("networks[{}]", index)))?;
+
+            network_names.push(nic.name);
+            reservations.push(reservation);
+            netmasks.push(netmask);
+            mac_overrides.push(mac_override);
+        }
+
+        let hardware = Hardware::from_definition(
+            definition.cpus,
+            definition.memory,
+            definition.disks,
+            definition.gui,
+        )?;
+
+        let mut provisioners = Vec::new();
+        for (index, provisioner) in definition.provisioners.into_iter().enumerate() {
+            provisioners.push(
+                Provisioner::from_definition(provisioner)
+                    .map_err(|e| e.within(&format!("provisioners[{}]", index)))?,
+            );
+        }
+
+        let mut forwarded_ports = Vec::new();
+        for (index, forwarded_port) in definition.forwarded_ports.into_iter().enumerate() {
+            forwarded_ports.push(
+                ForwardedPort::from_definition(forwarded_port)
+                    .map_err(|e| e.within(&format!("forwarded_ports[{}]", index)))?,
+            );
+        }
+
+        Ok(System {
+            name: definition.name,
+            network_names,
+            reservations,
+            netmasks,
+            mac_overrides,
+            base_box: definition.base_box,
+            tags: definition.tags,
+            hardware,
+            provisioners,
+            forwarded_ports,
+            synced_folders: definition
+                .synced_folders
+                .into_iter()
+                .map(SyncedFolder::from_definition)
+                .collect(),
+            router: definition.router,
+            lab_names: definition.lab_names,
+        })
+    }
+
+    pub fn network_names(&self) -> &[String] {
+        &self.network_names
+    }
+
+    /// The addresses given to each NIC, in order.
+    pub fn reservations(&self) -> &[Lease] {
+        &self.reservations
+    }
+
+    /// The MAC address of every NIC, in order. Unless overridden, each is derived from the
+    /// scenario name, system name and NIC index, so it's the same on every build.
+    pub fn mac_addresses(&self, scenario_name: &str) -> Vec<MacAddress> {
+        self.mac_overrides
             .iter()
-            .cloned()
-            .filter(|net| net.network_type == NetworkType::Internal)
-            .collect();
-
-        for net in internal_nets.into_iter() {
-            let leased_addr = net.get_address_lease()
-                .ok_or(format!(r#"Subnet for network "{}" does not have enough available addresses for all systems configured to use it."#, net.name.to_string()))?;
-
-            self.leased_network_addresses
-                .entry((&net.name).to_string())
-                .and_modify(|e| {
-                    e.push(leased_addr);
+            .enumerate()
+            .map(|(index, mac_override)| {
+                mac_override.unwrap_or_else(|| {
+                    MacAddress::generate(&format!("{}/{}/{}", scenario_name, self.name, index))
                 })
-                .or_insert_with(|| {
-                    return vec![leased_addr];
-                });
+            })
+            .collect()
+    }
+
+    /// Claims every statically reserved address of this system on its networks. This has to be
+    /// done for every system before any of them are resolved, so dynamic leases can't
+    /// take an address another system has reserved. Static IPs on public networks aren't
+    /// claimed, as Lab Builder doesn't know their subnets.
+    pub fn reserve_addresses(
+        &self,
+        scenario_networks: &[Rc<Network>],
+    ) -> Result<(), LabBuilderError> {
+        for (index, ((network_name, reservation), netmask)) in self
+            .network_names
+            .iter()
+            .zip(self.reservations.iter())
+            .zip(self.netmasks.iter())
+            .enumerate()
+        {
+            if reservation.ipv4.is_none() && reservation.ipv6.is_none() {
+                continue;
+            }
+
+            let network = scenario_networks
+                .iter()
+                .find(|&network| &network.name == network_name)
+                .ok_or_else(|| LabBuilderError::UnknownNetworkReference {
+                    location: Location::new(&format!("networks[{}]", index)),
+                    system: self.name.to_string(),
+                    network: network_name.to_string(),
+                })?;
+
+            match (network.network_type, reservation.ipv6, netmask) {
+                (NetworkType::Public, Some(_), _) => {
+                    return Err(LabBuilderError::invalid_value(
+                        &format!("networks[{}].ipv6", index),
+                        "NICs on Public networks can only be given an IPv4 address.",
+                    ))
+                }
+                (NetworkType::Public, None, _) => continue,
+                (_, _, Some(_)) => {
+                    return Err(LabBuilderError::invalid_value(
+                        &format!("networks[{}].netmask", index),
+                        "Only NICs on Public networks take a netmask, others use their network's subnet.",
+                    ))
+                }
+                _ => (),
+            }
+
+            if let Some(ipv4) = reservation.ipv4 {
+                network
+                    .reserve_address(ipv4)
+                    .map_err(|e| e.within(&format!("networks[{}]", index)))?;
+            }
+            if let Some(ipv6) = reservation.ipv6 {
+                network
+                    .reserve_ipv6_address(ipv6)
+                    .map_err(|e| e.within(&format!("networks[{}]", index)))?;
+            }
         }
 
         Ok(())
     }
+
+    /// Attaches this system to its networks and leases an address for every NIC on an internal
+    /// network. Reservations must already have been claimed with `reserve_addresses`.
+    pub fn resolve(
+        &self,
+        scenario_name: &str,
+        scenario_networks: &[Rc<Network>],
+        mode: AllocationMode,
+    ) -> Result<ResolvedSystem, LabBuilderError> {
+        let mut nics = Vec::new();
+        let macs = self.mac_addresses(scenario_name);
+
+        for (index, ((network_name, reservation), netmask)) in self
+            .network_names
+            .iter()
+            .zip(self.reservations.iter())
+            .zip(self.netmasks.iter())
+            .enumerate()
+        {
+            let net = scenario_networks
+                .iter()
+                .find(|&network| &network.name == network_name)
+                .ok_or_else(|| LabBuilderError::UnknownNetworkReference {
+                    location: Location::new(&format!("networks[{}]", index)),
+                    system: self.name.to_string(),
+                    network: network_name.to_string(),
+                })?;
+
+            let (lease, static_ip) = match net.network_type {
+                NetworkType::Internal | NetworkType::HostOnly | NetworkType::Nat => {
+                    let key = format!("{}/{}/{}", self.name, net.name, index);
+                    let lease = net
+                        .get_lease(reservation, mode, &key)
+                        .map_err(|e| e.within(&format!("networks[{}]", index)))?;
+                    (Some(lease), None)
+                }
+                NetworkType::Public => (None, reservation.ipv4),
+            };
+
+            nics.push(ResolvedNic {
+                network: Rc::clone(net),
+                lease,
+                static_ip,
+                netmask: *netmask,
+                mac: macs[index],
+            });
+        }
+
+        Ok(ResolvedSystem {
+            name: self.name.to_string(),
+            base_box: self.base_box.to_string(),
+            tags: self.tags.clone(),
+            hardware: self.hardware.clone(),
+            provisioners: self.provisioners.clone(),
+            forwarded_ports: self.forwarded_ports.clone(),
+            synced_folders: self.synced_folders.clone(),
+            router: self.router,
+            lab_names: self.lab_names,
+            routes: Vec::new(),
+            nics,
+        })
+    }
+}
+
+fn parse_reservation(nic: &NicDefinition) -> Result<Lease, LabBuilderError> {
+    let ipv4 = nic
+        .ip
+        .as_ref()
+        .map(|ip| {
+            ip.parse::<Ipv4Addr>().map_err(|_| {
+                LabBuilderError::invalid_value("ip", "Address is not a valid IPv4 address.")
+            })
+        })
+        .transpose()?;
+    let ipv6 = nic
+        .ipv6
+        .as_ref()
+        .map(|ip| {
+            ip.parse::<Ipv6Addr>().map_err(|_| {
+                LabBuilderError::invalid_value("ipv6", "Address is not a valid IPv6 address.")
+            })
+        })
+        .transpose()?;
+
+    Ok(Lease { ipv4, ipv6 })
+}
+
+fn parse_netmask(
+    nic: &NicDefinition,
+    ipv4: Option<Ipv4Addr>,
+) -> Result<Option<Ipv4Addr>, LabBuilderError> {
+    match (ipv4, &nic.netmask) {
+        (_, None) => Ok(None),
+        (None, Some(_)) => Err(LabBuilderError::invalid_value(
+            "netmask",
+            "A netmask can only be given along with a static IP.",
+        )),
+        (Some(ip), Some(netmask)) => netmask
+            .parse::<Ipv4Addr>()
+            .ok()
+            .filter(|&netmask| Ipv4Net::with_netmask(ip, netmask).is_ok())
+            .map(Some)
+            .ok_or_else(|| LabBuilderError::invalid_value("netmask", "Netmask is not valid.")),
+    }
+}
+
+fn parse_mac(mac: &str) -> Result<MacAddress, LabBuilderError> {
+    let mac = MacAddress::parse(mac).ok_or_else(|| {
+        LabBuilderError::invalid_value("mac", "MAC address is not six hex octets.")
+    })?;
+    match mac.is_unicast() {
+        true => Ok(mac),
+        false => Err(LabBuilderError::invalid_value(
+            "mac",
+            "MAC address is a multicast address, which a NIC can't use.",
+        )),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::lib::scenario::Scenario;
+    use crate::scenario::Scenario;
 
     #[test]
     fn parsing_system_without_name_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             networks = ["TestNet"]
             base_box = "Debian"
@@ -130,15 +412,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not read name of system".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("name")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_system_with_name_that_is_not_a_string_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = 42
             networks = ["TestNet"]
@@ -147,15 +429,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not read name of system as a string".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("name", "a string")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_system_without_base_box_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "Test System"
             networks = ["TestNet"]
@@ -163,15 +445,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not read base_box for system: Test System".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("base_box")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_system_with_base_box_that_is_not_a_string_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "Test System"
             networks = ["TestNet"]
@@ -180,15 +462,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not read base_box as a string for system: Test System".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("base_box", "a string")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_system_without_networks_array_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "Test System"
             base_box = "Debian"
@@ -196,15 +478,15 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not read networks for system: Test System".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::missing_field("networks")
         );
         Ok(())
     }
 
     #[test]
     fn parsing_system_with_networks_array_containing_something_other_than_strings_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let input = r#"
             name = "Test System"
             networks = [42]
@@ -213,15 +495,75 @@ mod tests {
         .parse::<Value>()?;
 
         assert_eq!(
-            *System::from_toml(&input).unwrap_err().description(),
-            "Could not parse networks for system: Test System".to_string()
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::wrong_type("networks[0]", "a network name or a NIC table")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_system_with_misspelt_field_should_fail_with_suggestion(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = ["TestNet"]
+            base_bx = "Debian"
+            "#
+        .parse::<Value>()?;
+
+        let error = System::from_toml(&input).unwrap_err();
+
+        assert_eq!(
+            error,
+            LabBuilderError::UnknownField {
+                location: Location::new("base_bx"),
+                suggestion: Some("base_box".to_string()),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            r#"Unknown field "base_bx". Did you mean "base_box"?"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_system_with_invalid_hardware_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            cpus = 0
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value("cpus", "A system needs at least one CPU.")
+        );
+
+        let input = r#"
+            name = "Test System"
+            networks = ["TestNet"]
+            base_box = "Debian"
+            disks = [{ size = "lots" }]
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "disks[0].size",
+                r#"Expected a number of megabytes or a size such as "20GB", found string "lots"."#
+            )
         );
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_with_1_public_network_should_not_lease_address(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_with_1_public_network_should_not_lease_address(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -235,17 +577,21 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let system = scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
 
-        let leased_addresses = &scenario.systems[0].leased_network_addresses;
-        assert_eq!(leased_addresses.is_empty(), true);
+        assert_eq!(system.nics.len(), 1);
+        assert_eq!(system.nics[0].lease, None);
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_with_1_internal_network_should_work(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_with_1_internal_network_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -260,25 +606,25 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let system = scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
 
-        let leased_addresses = &scenario.systems[0].leased_network_addresses;
-        assert_eq!(leased_addresses.len(), 1);
-        assert_eq!(leased_addresses.contains_key("TestNet"), true);
-        assert_eq!(
-            scenario.networks[0]
-                .subnet
-                .unwrap()
-                .contains(&leased_addresses["TestNet"][0]),
-            true
-        );
+        assert_eq!(system.nics.len(), 1);
+        assert_eq!(system.nics[0].network.name, "TestNet");
+        assert!(scenario.networks[0]
+            .subnet
+            .unwrap()
+            .contains(&system.nics[0].lease.unwrap().ipv4.unwrap()));
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_with_2_NICs_in_same_internal_network_should_work(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_with_2_nics_in_same_internal_network_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -293,37 +639,29 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let system = scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
 
-        let leased_addresses = &scenario.systems[0].leased_network_addresses;
-        assert_eq!(leased_addresses.len(), 1);
-        assert_eq!(leased_addresses.contains_key("TestNet"), true);
-        assert_eq!(leased_addresses["TestNet"].len(), 2);
-        assert_eq!(
-            scenario.networks[0]
-                .subnet
-                .unwrap()
-                .contains(&leased_addresses["TestNet"][0]),
-            true
-        );
-        assert_eq!(
-            scenario.networks[0]
-                .subnet
-                .unwrap()
-                .contains(&leased_addresses["TestNet"][1]),
-            true
-        );
-        assert_eq!(
-            leased_addresses["TestNet"][0] == leased_addresses["TestNet"][1],
-            false
-        );
+        assert_eq!(system.nics.len(), 2);
+        assert!(scenario.networks[0]
+            .subnet
+            .unwrap()
+            .contains(&system.nics[0].lease.unwrap().ipv4.unwrap()));
+        assert!(scenario.networks[0]
+            .subnet
+            .unwrap()
+            .contains(&system.nics[1].lease.unwrap().ipv4.unwrap()));
+        assert_ne!(system.nics[0].lease, system.nics[1].lease);
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_with_2_different_internal_networks_should_work(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_with_2_different_internal_networks_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -342,36 +680,31 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let system = scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
 
-        let leased_addresses = &scenario.systems[0].leased_network_addresses;
-        assert_eq!(leased_addresses.len(), 2);
-        assert_eq!(leased_addresses.contains_key("TestNet"), true);
-        assert_eq!(leased_addresses.contains_key("OtherNet"), true);
-        assert_eq!(leased_addresses["TestNet"].len(), 1);
-        assert_eq!(leased_addresses["OtherNet"].len(), 1);
-        assert_eq!(
-            scenario.networks[0]
-                .subnet
-                .unwrap()
-                .contains(&leased_addresses["TestNet"][0]),
-            true
-        );
-        assert_eq!(
-            scenario.networks[1]
-                .subnet
-                .unwrap()
-                .contains(&leased_addresses["OtherNet"][0]),
-            true
-        );
+        assert_eq!(system.nics.len(), 2);
+        assert_eq!(system.nics[0].network.name, "TestNet");
+        assert_eq!(system.nics[1].network.name, "OtherNet");
+        assert!(scenario.networks[0]
+            .subnet
+            .unwrap()
+            .contains(&system.nics[0].lease.unwrap().ipv4.unwrap()));
+        assert!(scenario.networks[1]
+            .subnet
+            .unwrap()
+            .contains(&system.nics[1].lease.unwrap().ipv4.unwrap()));
 
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_for_2_systems_in_same_internal_network_should_work(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_for_2_systems_in_same_internal_network_should_work(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -390,38 +723,37 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
-        scenario.systems[1].configure_networking(&scenario.networks)?;
-
-        let mut leased_addresses = Vec::new();
-        leased_addresses.push(&scenario.systems[0].leased_network_addresses);
-        leased_addresses.push(&scenario.systems[1].leased_network_addresses);
-
-        for x in leased_addresses.iter() {
-            assert_eq!(x.len(), 1);
-            assert_eq!(x.contains_key("TestNet"), true);
-            assert_eq!(x["TestNet"].len(), 1);
-            assert_eq!(
-                scenario.networks[0]
-                    .subnet
-                    .unwrap()
-                    .contains(&x["TestNet"][0]),
-                true
-            );
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let systems = [
+            scenario.systems[0].resolve(
+                &scenario.name,
+                &scenario.networks,
+                AllocationMode::Sequential,
+            )?,
+            scenario.systems[1].resolve(
+                &scenario.name,
+                &scenario.networks,
+                AllocationMode::Sequential,
+            )?,
+        ];
+
+        for x in systems.iter() {
+            assert_eq!(x.nics.len(), 1);
+            assert_eq!(x.nics[0].network.name, "TestNet");
+            assert!(scenario.networks[0]
+                .subnet
+                .unwrap()
+                .contains(&x.nics[0].lease.unwrap().ipv4.unwrap()));
         }
 
-        assert_eq!(
-            leased_addresses[0]["TestNet"][0] == leased_addresses[1]["TestNet"][0],
-            false
-        );
+        assert_ne!(systems[0].nics[0].lease, systems[1].nics[0].lease);
 
         Ok(())
     }
 
     #[test]
-    fn configuring_networking_for_2_systems_in_subnet_that_is_too_small_should_fail_with_msg(
-    ) -> Result<(), std::boxed::Box<std::error::Error>> {
+    fn resolving_system_for_2_systems_in_subnet_that_is_too_small_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
         let scenario_toml = r#"
             [scenario]
             name = "Test scenario"
@@ -444,16 +776,293 @@ mod tests {
         "#
         .parse::<Value>()?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        scenario.systems[0].configure_networking(&scenario.networks)?;
-        scenario.systems[1].configure_networking(&scenario.networks)?;
-        let result = scenario.systems[2].configure_networking(&scenario.networks);
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
+        scenario.systems[1].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
+        let result = scenario.systems[2].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        );
 
         assert_eq!(
-            result.unwrap_err().description(),
-            r#"Subnet for network "TestNet" does not have enough available addresses for all systems configured to use it."#.to_string()
+            result.unwrap_err(),
+            LabBuilderError::SubnetExhausted {
+                location: Location::new("networks[0]"),
+                network: "TestNet".to_string(),
+            }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn parsing_system_with_nic_table_with_invalid_ip_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = [{ name = "TestNet" }, { name = "OtherNet", ip = "192.168.0.300" }]
+            base_box = "Debian"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "networks[1].ip",
+                "Address is not a valid IPv4 address."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_system_with_nic_table_with_multicast_mac_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = [{ name = "TestNet", mac = "01:00:5e:00:00:01" }]
+            base_box = "Debian"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "networks[0].mac",
+                "MAC address is a multicast address, which a NIC can't use."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_system_with_nic_table_with_unknown_field_should_fail_with_suggestion(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = [{ name = "TestNet", ipv4 = "192.168.0.10" }]
+            base_box = "Debian"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::UnknownField {
+                location: Location::new("networks[0].ipv4"),
+                suggestion: Some("ipv6".to_string()),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_system_should_honour_reservations_regardless_of_system_order(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Workstation"
+            base_box = "Debian"
+            networks = ["TestNet"]
+            [[systems]]
+            name = "Domain Controller"
+            base_box = "Windows Server"
+            networks = [{ name = "TestNet", ip = "192.168.0.1", ipv6 = "fd00::10" }]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+            ipv6_subnet = "fd00::/64"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&scenario_toml)?.resolve()?;
+
+        assert_eq!(
+            scenario.systems[0].nics[0].lease,
+            Some(Lease {
+                ipv4: Some("192.168.0.2".parse()?),
+                ipv6: Some("fd00::1".parse()?),
+            })
+        );
+        assert_eq!(
+            scenario.systems[1].nics[0].lease,
+            Some(Lease {
+                ipv4: Some("192.168.0.1".parse()?),
+                ipv6: Some("fd00::10".parse()?),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_system_should_generate_mac_addresses_unless_overridden(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test system"
+            base_box = "Debian"
+            networks = [{ name = "TestNet" }, { name = "TestNet", mac = "08:00:27:AB:CD:EF" }]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+        let resolved_system = scenario.systems[0].resolve(
+            &scenario.name,
+            &scenario.networks,
+            AllocationMode::Sequential,
+        )?;
+
+        assert_eq!(
+            resolved_system.nics[0].mac,
+            MacAddress::generate("Test scenario/Test system/0")
+        );
+        assert_eq!(
+            resolved_system.nics[1].mac,
+            MacAddress([0x08, 0x00, 0x27, 0xab, 0xcd, 0xef])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_system_with_address_reserved_twice_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test system"
+            base_box = "Debian"
+            networks = [{ name = "TestNet", ip = "192.168.0.10" }]
+            [[systems]]
+            name = "Test system 2"
+            base_box = "Debian"
+            networks = [{ name = "TestNet", ip = "192.168.0.10" }]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&scenario_toml)?;
+
+        assert_eq!(
+            scenario.resolve().unwrap_err(),
+            LabBuilderError::invalid_value(
+                "systems[1].networks[0].ip",
+                r#"Address 192.168.0.10 is already reserved on network "TestNet"."#
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parsing_system_with_nic_table_with_netmask_but_no_ip_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let input = r#"
+            name = "Test System"
+            networks = [{ name = "TestNet", netmask = "255.255.255.0" }]
+            base_box = "Debian"
+            "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            System::from_toml(&input).unwrap_err(),
+            LabBuilderError::invalid_value(
+                "networks[0].netmask",
+                "A netmask can only be given along with a static IP."
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_system_with_static_ip_on_public_network_should_not_lease_it(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test system"
+            base_box = "Debian"
+            networks = [{ name = "Office", ip = "192.0.2.10", netmask = "255.255.255.0" }]
+            [[networks]]
+            name = "Office"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        let scenario = Scenario::from_toml(&scenario_toml)?.resolve()?;
+        let nic = &scenario.systems[0].nics[0];
+
+        assert_eq!(nic.lease, None);
+        assert_eq!(nic.static_ip, Some("192.0.2.10".parse()?));
+        assert_eq!(nic.netmask, Some("255.255.255.0".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn resolving_system_with_public_only_nic_fields_on_other_networks_should_fail_with_msg(
+    ) -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test system"
+            base_box = "Debian"
+            networks = [{ name = "TestNet", ip = "192.168.0.10", netmask = "255.255.255.0" }]
+            [[networks]]
+            name = "TestNet"
+            type = "Internal"
+            subnet = "192.168.0.0/24"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Scenario::from_toml(&scenario_toml)?.resolve().unwrap_err(),
+            LabBuilderError::invalid_value(
+                "systems[0].networks[0].netmask",
+                "Only NICs on Public networks take a netmask, others use their network's subnet."
+            )
+        );
+
+        let scenario_toml = r#"
+            [scenario]
+            name = "Test scenario"
+            [[systems]]
+            name = "Test system"
+            base_box = "Debian"
+            networks = [{ name = "Office", ipv6 = "2001:db8::10" }]
+            [[networks]]
+            name = "Office"
+            type = "Public"
+        "#
+        .parse::<Value>()?;
+
+        assert_eq!(
+            Scenario::from_toml(&scenario_toml)?.resolve().unwrap_err(),
+            LabBuilderError::invalid_value(
+                "systems[0].networks[0].ipv6",
+                "NICs on Public networks can only be given an IPv4 address."
+            )
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,49 @@
+use std::collections::HashSet;
+
+/// Sanitises each name, in order, and tells apart names that sanitise to the same thing, or to
+/// one of `reserved`, by appending `separator` and a numeric suffix to every name after the first.
+pub fn unique_names<'a, I, F>(
+    names: I,
+    sanitise: F,
+    separator: &str,
+    reserved: &[&str],
+) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+    F: Fn(&str) -> String,
+{
+    let mut taken: HashSet<String> = reserved.iter().map(|name| name.to_string()).collect();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let sanitised = sanitise(name);
+            let mut candidate = sanitised.to_string();
+            let mut suffix = 1;
+            while taken.contains(&candidate) {
+                suffix += 1;
+                candidate = format!("{}{}{}", sanitised, separator, suffix);
+            }
+            taken.insert(candidate.to_string());
+            candidate
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_names_should_suffix_collisions_and_reserved_names() {
+        assert_eq!(
+            unique_names(
+                vec!["a", "A", "b", "a"],
+                |name| name.to_lowercase(),
+                "~",
+                &["b"]
+            ),
+            vec!["a", "a~2", "b~2", "a~3"]
+        );
+    }
+}
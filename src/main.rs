@@ -1,17 +1,32 @@
-mod lib;
+use lab_builder::backend::BackendRegistry;
+use lab_builder::diagnostic::Diagnostic;
+use lab_builder::diagram::Diagram;
+use lab_builder::input_format::InputFormat;
+use lab_builder::plan::Plan;
+use lab_builder::provider::Provider;
+use lab_builder::resolved_scenario::ResolvedScenario;
+use lab_builder::scenario::Scenario;
 
-use toml::Value;
-
-use crate::lib::scenario::Scenario;
-
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
+use std::env;
 use std::fs;
 use std::path::Path;
+use std::process;
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+    let backends = BackendRegistry::default();
+    let backend_names = backends.names();
 
-fn main() -> Result<(), std::boxed::Box<std::error::Error>> {
     let arg_matches = App::new("Lab Builder")
-        .settings(&vec![AppSettings::SubcommandRequired])
+        .settings(&[AppSettings::SubcommandRequired])
         .version("0.1")
         .author("Daniel Murphy <danhatesnumbers@gmail.com>")
         .subcommand(
@@ -23,53 +38,197 @@ fn main() -> Result<(), std::boxed::Box<std::error::Error>> {
                         .required(true)
                         .takes_value(true)
                         .value_name("SCENARIO_PATH")
-                        .help("path to Scenario to plan in TOML format"),
+                        .help("path to Scenario to plan in TOML, YAML or JSON format"),
+                )
+                .arg(input_format_arg())
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .help("output format for the plan"),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("vagrantfile")
-                .about("build vagrantfile from Scenario")
+            SubCommand::with_name("diagram")
+                .about("draw topology diagram of Scenario")
                 .arg(
                     Arg::with_name("scenario")
                         .short("s")
                         .required(true)
                         .takes_value(true)
                         .value_name("SCENARIO_PATH")
-                        .help("path to Scenario to build in TOML format"),
+                        .help("path to Scenario to draw in TOML, YAML or JSON format"),
+                )
+                .arg(input_format_arg())
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["dot", "mermaid"])
+                        .default_value("dot")
+                        .help("output format for the diagram"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("build lab tool files from Scenario")
+                .arg(
+                    Arg::with_name("scenario")
+                        .short("s")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("SCENARIO_PATH")
+                        .help("path to Scenario to build in TOML, YAML or JSON format"),
+                )
+                .arg(input_format_arg())
+                .arg(
+                    Arg::with_name("backend")
+                        .short("b")
+                        .long("backend")
+                        .takes_value(true)
+                        .value_name("BACKEND")
+                        .possible_values(&backend_names)
+                        .default_value("vagrant")
+                        .help("backend to generate output with"),
+                )
+                .arg(
+                    Arg::with_name("provider")
+                        .short("p")
+                        .long("provider")
+                        .takes_value(true)
+                        .value_name("PROVIDER")
+                        .possible_values(&["virtualbox", "libvirt"])
+                        .help("provider to build for, overriding the Scenario's provider"),
                 )
                 .arg(
                     Arg::with_name("output")
                         .short("o")
-                        .required(true)
                         .takes_value(true)
-                        .value_name("OUTPUT_PATH")
-                        .help("output path for vagrantfile"),
+                        .value_name("OUTPUT_DIR")
+                        .default_value(".")
+                        .help("directory to write generated files to"),
                 ),
         )
         .get_matches();
 
     if let Some(plan) = arg_matches.subcommand_matches("plan") {
-        let scenario_path = Path::new(plan.value_of("scenario").unwrap());
+        let scenario = load_scenario(plan, Path::new("."), None)?;
 
-        let scenario_toml = fs::read_to_string(scenario_path)?.parse::<Value>()?;
-        println!("{:?}", Scenario::from_toml(&scenario_toml)?);
+        let scenario_plan = Plan::from_scenario(&scenario);
+        match plan.value_of("format") {
+            Some("json") => println!("{}", scenario_plan.to_json()?),
+            _ => println!("{}", scenario_plan.to_text()),
+        }
     };
 
-    if let Some(vagrantfile) = arg_matches.subcommand_matches("vagrantfile") {
-        let scenario_path = Path::new(vagrantfile.value_of("scenario").unwrap());
-
-        let scenario_toml = fs::read_to_string(scenario_path)?.parse::<Value>()?;
+    if let Some(diagram) = arg_matches.subcommand_matches("diagram") {
+        let scenario = load_scenario(diagram, Path::new("."), None)?;
 
-        let mut scenario = Scenario::from_toml(&scenario_toml)?;
-        for system in scenario.systems.iter_mut() {
-            system.configure_networking(&scenario.networks)?;
+        let scenario_diagram = Diagram::from_scenario(&scenario)?;
+        match diagram.value_of("format") {
+            Some("mermaid") => println!("{}", scenario_diagram.to_mermaid()),
+            _ => println!("{}", scenario_diagram.to_dot()),
         }
+    };
+
+    if let Some(build) = arg_matches.subcommand_matches("build") {
+        let output_dir = Path::new(build.value_of("output").unwrap());
+        let provider = build.value_of("provider").and_then(Provider::from_name);
+        let scenario = load_scenario(build, output_dir, provider)?;
 
-        let output = scenario.to_vagrantfile()?;
+        let backend = backends
+            .get(build.value_of("backend").unwrap())
+            .ok_or("Unknown backend")?;
 
-        let vagrantfile_path = Path::new(vagrantfile.value_of("output").unwrap());
-        fs::write(vagrantfile_path, output)?;
+        fs::create_dir_all(output_dir)?;
+        for output_file in backend.generate(&scenario)? {
+            fs::write(output_dir.join(&output_file.path), output_file.contents)?;
+        }
     };
 
     Ok(())
 }
+
+fn input_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("input-format")
+        .long("input-format")
+        .takes_value(true)
+        .value_name("INPUT_FORMAT")
+        .possible_values(&["toml", "yaml", "json"])
+        .help("format of the Scenario file, picked from its extension when omitted")
+}
+
+/// Loads the scenario, pointing the local files it refers to at paths relative to `output_dir`
+/// and building it for `provider` when one is given.
+fn load_scenario(
+    arg_matches: &ArgMatches,
+    output_dir: &Path,
+    provider: Option<Provider>,
+) -> Result<ResolvedScenario, std::boxed::Box<dyn std::error::Error>> {
+    let scenario_path = Path::new(arg_matches.value_of("scenario").unwrap());
+    let input_format = arg_matches
+        .value_of("input-format")
+        .and_then(InputFormat::from_name)
+        .or_else(|| InputFormat::from_path(scenario_path))
+        .unwrap_or(InputFormat::Toml);
+
+    let scenario_source = fs::read_to_string(scenario_path)?;
+    let scenario_value = input_format.parse(&scenario_source)?;
+
+    // Paths can only be mapped back to lines and columns in TOML source.
+    let source = match input_format {
+        InputFormat::Toml => Some(scenario_source.as_str()),
+        _ => None,
+    };
+
+    let (mut scenario, warnings) = match Scenario::validate(&scenario_value) {
+        Ok(validated) => validated,
+        Err(diagnostics) => {
+            report_diagnostics(diagnostics, source);
+            return Err("Scenario failed validation".into());
+        }
+    };
+    report_diagnostics(warnings, source);
+
+    let current_dir = env::current_dir()?;
+    let scenario_dir = current_dir.join(scenario_path.parent().unwrap_or_else(|| Path::new("")));
+    let file_errors = scenario.resolve_local_files(&scenario_dir, &current_dir.join(output_dir));
+    if !file_errors.is_empty() {
+        report_diagnostics(file_errors, source);
+        return Err("Scenario failed validation".into());
+    }
+
+    if let Some(provider) = provider {
+        scenario.provider = provider;
+    }
+
+    let interfaces_dir = Path::new("/sys/class/net");
+    if interfaces_dir.is_dir() {
+        report_diagnostics(
+            scenario.check_bridge_interfaces(scenario.provider, interfaces_dir),
+            source,
+        );
+    }
+
+    let resolved_scenario = scenario.resolve().map_err(|e| match source {
+        Some(source) => e.with_source(source),
+        None => e,
+    })?;
+
+    Ok(resolved_scenario)
+}
+
+fn report_diagnostics(diagnostics: Vec<Diagnostic>, source: Option<&str>) {
+    for diagnostic in diagnostics {
+        match source {
+            Some(source) => eprintln!("{}", diagnostic.with_source(source)),
+            None => eprintln!("{}", diagnostic),
+        }
+    }
+}